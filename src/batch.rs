@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::error::{Result, TwdlError};
+
+// Broadcasters for `twdl channels`, each downloaded with its own output dir, range and filters.
+// Dates take the same formats as the channel command's flags.
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BatchFile {
+    // `[[channel]]` tables in toml
+    #[serde(rename = "channel")]
+    pub channels: Vec<BatchChannel>
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BatchChannel {
+    pub login: String,
+
+    // Defaults to a folder named after the login in the command's output dir
+    pub output: Option<String>,
+
+    // Defaults to the broadcaster's creation date
+    pub start: Option<String>,
+
+    // Defaults to now
+    pub end: Option<String>,
+
+    // Only keep fetched clips created in this range, like --created-after/--created-before
+    pub created_after: Option<String>,
+
+    pub created_before: Option<String>,
+
+    // Keeps only the N most viewed clips in the range
+    pub limit: Option<usize>
+}
+
+// Parsed as toml when the file ends in .toml in any case, otherwise as json
+pub async fn load_batch(path: &Path) -> Result<BatchFile> {
+    let contents = fs::read_to_string(path).await
+        .map_err(|err| TwdlError::io(format!("Failed to read {}", path.display()), err))?;
+    match path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")) {
+        true => Ok(toml::from_str(&contents)?),
+        false => Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn toml_extension_is_case_insensitive() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("Batch.TOML");
+        std::fs::write(&path, "[[channel]]\nlogin = \"somestreamer\"\nlimit = 5\n").unwrap();
+        let batch = load_batch(&path).await.unwrap();
+        assert_eq!(batch.channels.len(), 1);
+        assert_eq!(batch.channels[0].login, "somestreamer");
+        assert_eq!(batch.channels[0].limit, Some(5));
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll}
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{fs::{self, File}, io::{AsyncReadExt, AsyncWrite}};
+
+use crate::error::{Result, TwdlError};
+
+// SHA-256 sidecars written next to clips, in the `sha256sum` format so `sha256sum -c` can check them too
+
+// Hashes everything written through it, so a download is hashed without reading the file back
+pub struct HashingWriter<W> {
+    inner: W,
+
+    hasher: Sha256
+}
+
+impl<W> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter { inner, hasher: Sha256::new() }
+    }
+
+    // Lowercase hex digest of everything written so far
+    pub fn finish(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        // Only what the inner writer accepted, a partial write is followed by another call with the rest
+        if let Poll::Ready(Ok(written)) = poll {
+            self.hasher.update(&buf[..written]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// For files that weren't written in order, like split downloads, or were changed afterwards
+pub async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await
+        .map_err(|err| TwdlError::io(format!("Failed to open {}", path.display()), err))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await
+            .map_err(|err| TwdlError::io(format!("Failed to read {}", path.display()), err))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// `{file}.sha256`
+pub fn sidecar_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+pub async fn write_sidecar(file: &Path, digest: &str) -> Result<()> {
+    let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let path = sidecar_path(file);
+    fs::write(&path, format!("{digest}  {name}\n")).await
+        .map_err(|err| TwdlError::io(format!("Failed to write checksum {}", path.display()), err))
+}
+
+// None when there's no sidecar for `file`
+pub async fn read_sidecar(file: &Path) -> Option<String> {
+    let contents = fs::read_to_string(sidecar_path(file)).await.ok()?;
+    contents.split_whitespace().next().map(str::to_lowercase)
+}
@@ -1,23 +1,147 @@
-use clap::{command, Args, Parser, Subcommand};
+use chrono::TimeDelta;
+use clap::{command, Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use crate::DEFAULT_USER_AGENT;
 
 #[derive(Parser, Debug)]
 #[command(name = "twdl", version, about = "Downloads twitch clips")]
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
+
+    #[arg(long = "gql-client-id", global = true, help = "Client-ID sent to twitch's GQL API, falls back to TWITCH_GQL_CLIENT_ID or the web player's ID")]
+    pub gql_client_id: Option<String>,
+
+    #[arg(long = "gql-endpoint", global = true, hide = true, help = "GQL API URL, falls back to TWITCH_GQL_ENDPOINT. Mostly useful for testing")]
+    pub gql_endpoint: Option<String>,
+
+    #[arg(long = "cache-sources", global = true, help = "Keep resolved source URLs in the OS cache dir for a few minutes, so a --link run followed by a download resolves each clip once")]
+    pub cache_sources: bool,
+
+    #[arg(long = "refresh-cache", global = true, help = "Forget cached broadcaster login lookups before running")]
+    pub refresh_cache: bool,
+
+    #[arg(long = "proxy", global = true, help = "HTTP(S) or SOCKS5 proxy URL used for all requests, HTTPS_PROXY is respected when unset")]
+    pub proxy: Option<String>,
+
+    #[arg(long = "proxy-list", global = true, conflicts_with = "proxy", help = "File with one proxy URL per line, clip downloads take turns using them while GQL and helix requests go direct")]
+    pub proxy_list: Option<String>,
+
+    #[arg(long = "user-agent", global = true, default_value = DEFAULT_USER_AGENT, help = "User-Agent header sent with every request")]
+    pub user_agent: String,
+
+    #[arg(long = "timeout", global = true, help = "Seconds before a connection attempt or a single download is abandoned")]
+    pub timeout: Option<u64>,
+
+    #[arg(long = "resolve-timeout", global = true, help = "Seconds before looking up a clip's source files is abandoned, the clip then counts as failed")]
+    pub resolve_timeout: Option<u64>,
+
+    #[arg(long = "retries", global = true, help = "Number of times a failed download is retried, default=2")]
+    pub retries: Option<u32>,
+
+    #[arg(long = "retry-failed-passes", global = true, help = "Passes made over failed clips once the rest of a channel or game download finishes, default=1")]
+    pub retry_failed_passes: Option<u32>,
+
+    #[arg(long = "retry-budget", global = true, help = "Most retries made across the whole run, further failures aren't retried")]
+    pub retry_budget: Option<u64>,
+
+    #[arg(short = 'q', long = "quality", global = true, help = "Preferred resolution, e.g. 720. Falls back to the best available")]
+    pub quality: Option<u32>,
+
+    #[arg(long = "min-quality", global = true, help = "Skip clips whose best resolution is below this, e.g. 720")]
+    pub min_quality: Option<u32>,
+
+    #[arg(short = 'v', long = "verbose", global = true, action = clap::ArgAction::Count, help = "Log debug output, repeat for trace output")]
+    pub verbose: u8,
+
+    #[arg(long = "color", global = true, value_enum, default_value_t = ColorMode::Auto, help = "Whether to use colors and draw progress bars, never suits captured logs")]
+    pub color: ColorMode,
+
+    #[arg(long = "log-file", global = true, help = "Also append log lines to this file")]
+    pub log_file: Option<String>,
+
+    #[arg(long = "progress-fd", global = true, help = "Write download progress as json lines (started, progress, completed, failed) to this file descriptor, 2 for stderr")]
+    pub progress_fd: Option<i32>,
+
+    #[arg(long = "log-format", global = true, value_enum, default_value_t = LogFormat::Text, help = "Format of log lines on the console and in --log-file")]
+    pub log_format: LogFormat,
+
+    #[arg(long = "split", global = true, value_parser = clap::value_parser!(u32).range(1..=16), help = "Download each file over this many connections when the server allows it, default=1")]
+    pub split: Option<u32>,
+
+    #[arg(long = "max-rate", global = true, value_parser = clap::value_parser!(u64).range(1..), help = "Limit the combined download speed to this many bytes per second")]
+    pub max_rate: Option<u64>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+
+    // One json object per line with ts, level, message and clip_id when there is one
+    Json
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    // Colors unless NO_COLOR is set
+    Auto,
+
+    Always,
+
+    // No colors and no progress bars, only log lines
+    Never
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthMode {
+    // Client credentials only, enough for public clips
+    App,
+
+    // Authorize as a twitch user through a device code, the token is cached for later runs
+    User
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkFormat {
+    Plain,
+
+    // The selected source file's slug, quality, frame_rate and url as one json object, an array of them with --all-qualities
+    Json
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Clip(ClipCommandArgs),
 
-    Channel(ChannelCommandArgs)
+    Channel(ChannelCommandArgs),
+
+    Info(InfoCommandArgs),
+
+    User(UserCommandArgs),
+
+    Game(GameCommandArgs),
+
+    #[command(about = "Store twitch credentials in the OS keyring")]
+    Login(LoginCommandArgs),
+
+    #[command(about = "Check downloaded clips against the size of their source files")]
+    Verify(VerifyCommandArgs),
+
+    #[command(about = "Find a broadcaster's clips whose title matches a keyword. Titles are filtered locally, every clip in the range is fetched")]
+    Search(SearchCommandArgs),
+
+    #[command(about = "Download clips for every broadcaster listed in a toml or json file, each with its own output dir, range and filters")]
+    Channels(ChannelsCommandArgs),
+
+    #[command(hide = true, about = "Print a shell completion script")]
+    Completions(CompletionsCommandArgs)
 }
 
 #[derive(Args, Debug)]
 pub struct ClipCommandArgs {
-     #[arg(short = 'o', long = "output", default_value_t = String::from("."), help = "Output dir to download clip to")]
-    pub output: String,
+    #[arg(short = 'o', long = "output", help = "Output dir to download clip to, - writes the clip to standard output like --stdout [default: .]")]
+    pub output: Option<String>,
 
     #[arg(short = 'L', long = "link", help = "Skip download and print the source file URL")]
     pub link: bool,
@@ -25,19 +149,254 @@ pub struct ClipCommandArgs {
     #[arg(short = 'm', long = "metadata", help = "Download json metadata alongside the clip")]
     pub metadata: bool,
 
-    #[arg(short = 'c', long = "credentials", help = "Path to a json file containing client_id and client_secret")]
+    #[arg(long = "overwrite-metadata", requires = "metadata", help = "Rewrite metadata files that already exist instead of keeping them")]
+    pub overwrite_metadata: bool,
+
+    #[arg(long = "compact-metadata", requires = "metadata", help = "Write metadata files as compact json instead of indented")]
+    pub compact_metadata: bool,
+
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
     pub credentials: Option<String>,
 
+    #[arg(long = "auth", value_enum, default_value_t = AuthMode::App, help = "Authenticate as the app, or as a twitch user for data only they can see")]
+    pub auth: AuthMode,
+
+    #[arg(long = "m3u", requires = "link", help = "Write an extended M3U playlist for the clip (title and duration require credentials)")]
+    pub m3u: Option<String>,
+
+    #[arg(long = "format", value_enum, default_value_t = LinkFormat::Plain, requires = "link", help = "How --link prints the source file")]
+    pub format: LinkFormat,
+
+    #[arg(long = "checksums", conflicts_with_all = ["link", "stdout"], help = "Write the clip's SHA-256 to {id}.mp4.sha256, checked by the verify command")]
+    pub checksums: bool,
+
+    #[arg(long = "all-qualities", conflicts_with_all = ["stdout", "trim_start", "trim_end", "audio_only"], help = "Download every source file as {id}-{quality}p{fps}.mp4, or with --link print each as \"quality frame_rate url\", best first")]
+    pub all_qualities: bool,
+
+    #[arg(short = 'Q', long = "quiet", help = "Only log errors")]
+    pub quiet: bool,
+
+    #[arg(long = "stdout", conflicts_with_all = ["output", "link", "metadata", "audio_only", "trim_start", "trim_end"], help = "Write the clip to standard output instead of a file, logs stay on stderr")]
+    pub stdout: bool,
+
+    #[arg(long = "audio-only", help = "Extract the clip's audio after downloading, requires ffmpeg")]
+    pub audio_only: bool,
+
+    #[arg(long = "audio-format", default_value = "mp3", requires = "audio_only", help = "File extension of the extracted audio, ffmpeg picks the codec from it")]
+    pub audio_format: String,
+
+    #[arg(long = "delete-video", requires = "audio_only", help = "Remove the video once its audio was extracted")]
+    pub delete_video: bool,
+
+    #[arg(long = "trim-start", value_parser = parse_offset, conflicts_with = "link", help = "Also write {id}_trimmed.mp4 starting at this offset, in seconds or mm:ss. Requires ffmpeg")]
+    pub trim_start: Option<f64>,
+
+    #[arg(long = "trim-end", value_parser = parse_offset, conflicts_with = "link", help = "Also write {id}_trimmed.mp4 ending at this offset, in seconds or mm:ss. Requires ffmpeg")]
+    pub trim_end: Option<f64>,
+
     pub clip: String
 }
 
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, header_value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), header_value.trim().to_string())),
+        _ => Err(format!("{value} isn't a \"Name: value\" header"))
+    }
+}
+
+// Seconds from `ss`, `mm:ss` or `hh:mm:ss`, the seconds may have a fraction
+fn parse_offset(value: &str) -> Result<f64, String> {
+    let invalid = || format!("{value} isn't a time in seconds or mm:ss");
+    let mut seconds = 0.0;
+    for (i, part) in value.split(':').enumerate() {
+        if i > 2 {
+            return Err(invalid());
+        }
+        let part: f64 = part.parse().map_err(|_| invalid())?;
+        if !part.is_finite() || part < 0.0 {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Ok(seconds)
+}
+
+// A whole number followed by s, m, h, d or w, e.g. 7d
+fn parse_since(value: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("{value} isn't a duration like 30m, 12h, 7d or 2w");
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let delta = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None
+    };
+    delta.filter(|delta| *delta > TimeDelta::zero()).ok_or_else(invalid)
+}
+
 #[derive(Args, Debug)]
 pub struct ChannelCommandArgs {
-    #[arg(short = 'o', long = "output", default_value_t = String::from("."), help = "Path to directory to store the clips")]
-    pub output: String,
+    #[arg(short = 'o', long = "output", help = "Path to directory to store the clips [default: .]")]
+    pub output: Option<String>,
+
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
+    pub credentials: Option<String>,
+
+    #[arg(long = "auth", value_enum, default_value_t = AuthMode::App, help = "Authenticate as the app, or as a twitch user for data only they can see")]
+    pub auth: AuthMode,
+
+    #[arg(short = 'i', long = "broadcaster-id", help = "Numeric broadcaster ID")]
+    pub broadcaster_id: Option<u32>,
+
+    #[arg(short = 'l', long = "broadcaster-login", value_delimiter = ',', conflicts_with = "broadcaster_id", help = "Broadcaster login, repeat or separate with commas for several broadcasters")]
+    pub broadcaster_login: Vec<String>,
+
+    #[arg(long = "broadcasters-file", conflicts_with = "broadcaster_id", help = "File with one broadcaster login per line, each downloaded into its own folder")]
+    pub broadcasters_file: Option<String>,
+
+    #[arg(long = "batch", conflicts_with_all = ["broadcaster_id", "broadcaster_login", "broadcasters_file", "start_timestamp", "end_timestamp", "since", "since_last_run", "interactive"], help = "File with one clip URL or slug per line to download instead of listing a broadcaster's clips. Works without credentials unless an option needs the clips' details")]
+    pub batch: Option<String>,
+
+    #[arg(short = 's', long = "start", help = "Start of datetime range (If no end provided, defaults to 1 week)")]
+    pub start_timestamp: Option<String>,
+
+    #[arg(short = 'e', long = "end", help = "End of datetime range, requires a start time")]
+    pub end_timestamp: Option<String>,
+
+    #[arg(long = "since", value_parser = parse_since, conflicts_with_all = ["start_timestamp", "end_timestamp"], help = "Fetch clips from this long ago until now, e.g. 7d. Units are s, m, h, d and w")]
+    pub since: Option<TimeDelta>,
+
+    #[arg(long = "created-after", help = "Only keep fetched clips created after this time, the --start/--end range is still what gets fetched")]
+    pub created_after: Option<String>,
+
+    #[arg(long = "created-before", help = "Only keep fetched clips created before this time, the --start/--end range is still what gets fetched")]
+    pub created_before: Option<String>,
+
+    #[arg(short = 'C', long = "chunk-size", visible_alias = "concurrency", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Number of clips downloaded at once, default=10")]
+    pub chunk_size: Option<usize>,
+
+    #[arg(short = 'L', long = "link", help = "Skip downloads and print the source file URLs to stdout")]
+    pub link: bool,
+
+    #[arg(short = 'm', long = "metadata", help = "Download json metadata alongside the clip")]
+    pub metadata: bool,
+
+    #[arg(long = "overwrite-metadata", requires = "metadata", help = "Rewrite metadata files that already exist instead of keeping them")]
+    pub overwrite_metadata: bool,
+
+    #[arg(long = "compact-metadata", requires = "metadata", help = "Write metadata files as compact json instead of indented")]
+    pub compact_metadata: bool,
+
+    #[arg(long = "m3u", help = "Skip downloads and write an extended M3U playlist of the source file URLs")]
+    pub m3u: Option<String>,
+
+    #[arg(long = "exec", help = "Command run after each clip downloads, with {path}, {id} and {title} filled in")]
+    pub exec: Option<String>,
+
+    #[arg(long = "exec-concurrency", default_value_t = 1, requires = "exec", help = "Maximum number of --exec commands running at once")]
+    pub exec_concurrency: usize,
+
+    #[arg(long = "sync", help = "Skip clips already recorded in the output directory's manifest.json")]
+    pub sync: bool,
+
+    #[arg(long = "since-last-run", help = "Start after the newest clip in the output directory's manifest.json, falls back to --start or --since")]
+    pub since_last_run: bool,
+
+    #[arg(long = "failures", help = "Write clips that couldn't be downloaded to this file, as json if it ends in .json")]
+    pub failures: Option<String>,
+
+    #[arg(long = "prescan", help = "Find every clip's size before downloading to show the total, at the cost of an extra request per clip")]
+    pub prescan: bool,
+
+    #[arg(long = "checksums", conflicts_with = "link", help = "Write each clip's SHA-256 to {id}.mp4.sha256, checked by the verify command")]
+    pub checksums: bool,
+
+    #[arg(long = "thumbnails", help = "Save each clip's thumbnail as {id}.jpg next to it")]
+    pub thumbnails: bool,
+
+    #[arg(long = "embed-metadata", help = "Tag each clip with its title, creator and date, requires ffmpeg")]
+    pub embed_metadata: bool,
+
+    #[arg(long = "nfo", help = "Write a Kodi/Jellyfin {id}.nfo sidecar for each clip")]
+    pub nfo: bool,
+
+    #[arg(long = "audio-only", help = "Extract each clip's audio after downloading, requires ffmpeg")]
+    pub audio_only: bool,
+
+    #[arg(long = "audio-format", default_value = "mp3", requires = "audio_only", help = "File extension of the extracted audio, ffmpeg picks the codec from it")]
+    pub audio_format: String,
+
+    #[arg(long = "delete-video", requires = "audio_only", help = "Remove the videos once their audio was extracted")]
+    pub delete_video: bool,
+
+    #[arg(long = "concat", conflicts_with = "audio_only", help = "Join the downloaded clips, in order, into this file. Re-encodes everything, so it's slow. Requires ffmpeg")]
+    pub concat: Option<String>,
+
+    #[arg(long = "concat-only", requires = "concat", help = "Delete the individual clips once they were joined")]
+    pub concat_only: bool,
+
+    #[arg(long = "gif", help = "Make a short {id}.gif preview of each clip, skipped when ffmpeg isn't found")]
+    pub gif: bool,
+
+    #[arg(long = "gif-duration", default_value_t = 5.0, requires = "gif", value_parser = parse_offset, help = "Seconds from the start of each clip covered by its GIF")]
+    pub gif_duration: f64,
+
+    #[arg(long = "gif-width", default_value_t = 320, requires = "gif", help = "Width of the GIFs in pixels, the height keeps the aspect ratio")]
+    pub gif_width: u32,
+
+    #[arg(long = "gif-fps", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=30), requires = "gif", help = "Frame rate of the GIFs")]
+    pub gif_fps: u32,
+
+    #[arg(long = "discord-webhook", help = "Post a summary of the run to this Discord webhook once it finishes")]
+    pub discord_webhook: Option<String>,
+
+    #[arg(long = "webhook", help = "POST the run's report as json to this URL once it finishes")]
+    pub webhook: Option<String>,
+
+    #[arg(long = "webhook-header", requires = "webhook", value_parser = parse_header, help = "Extra \"Name: value\" header sent with --webhook, can be repeated")]
+    pub webhook_headers: Vec<(String, String)>,
+
+    #[arg(long = "interactive", help = "Pick which of the fetched clips to download from a list")]
+    pub interactive: bool,
 
-    #[arg(short = 'c', long = "credentials", help = "Path to a json file containing client_id and client_secret")]
-    pub credentials: String,
+    #[arg(long = "max-downloads", help = "Refuse to download more clips than this unless --yes is passed")]
+    pub max_downloads: Option<usize>,
+
+    #[arg(short = 'y', long = "yes", help = "Go ahead with downloads over --max-downloads and skip the confirmation for large downloads")]
+    pub yes: bool,
+
+    #[arg(long = "number", help = "Prefix file names with each clip's position, e.g. 007_{id}.mp4, so they sort in download order")]
+    pub number: bool,
+
+    #[arg(long = "page-delay", default_value_t = 0, help = "Milliseconds to wait between pages of clips from twitch")]
+    pub page_delay: u64,
+
+    #[arg(long = "json", conflicts_with_all = ["link", "m3u"], help = "Print a json summary of the run to stdout, with the outcome of every clip")]
+    pub json: bool
+}
+
+#[derive(Args, Debug)]
+pub struct InfoCommandArgs {
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
+    pub credentials: Option<String>,
+
+    #[arg(short = 'j', long = "json", help = "Print the raw clip metadata as json")]
+    pub json: bool,
+
+    pub clip: String
+}
+
+#[derive(Args, Debug)]
+pub struct SearchCommandArgs {
+    #[arg(short = 'o', long = "output", help = "Path to directory to store the matching clips [default: .]")]
+    pub output: Option<String>,
+
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
+    pub credentials: Option<String>,
 
     #[arg(short = 'i', long = "broadcaster-id", help = "Numeric broadcaster ID")]
     pub broadcaster_id: Option<u32>,
@@ -45,18 +404,251 @@ pub struct ChannelCommandArgs {
     #[arg(short = 'l', long = "broadcaster-login", help = "Broadcaster login")]
     pub broadcaster_login: Option<String>,
 
-    #[arg(short = 's', long = "start", help = "Start of datetime range (If no end provided, defaults to 1 week)")]
+    #[arg(short = 's', long = "start", help = "Start of datetime range (defaults to the broadcaster's creation date)")]
     pub start_timestamp: Option<String>,
 
     #[arg(short = 'e', long = "end", help = "End of datetime range, requires a start time")]
     pub end_timestamp: Option<String>,
 
-    #[arg(short = 'C', long = "chunk-size", help = "Number of clips fetched per page, default=20 max=100")]
+    #[arg(short = 'C', long = "chunk-size", visible_alias = "concurrency", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Number of clips downloaded at once, default=10")]
+    pub chunk_size: Option<usize>,
+
+    #[arg(short = 'r', long = "regex", help = "Treat the keyword as a regular expression")]
+    pub regex: bool,
+
+    #[arg(long = "case-sensitive", help = "Match the keyword's case exactly")]
+    pub case_sensitive: bool,
+
+    #[arg(long = "list", help = "Print the matching clips' URLs and titles instead of downloading them")]
+    pub list: bool,
+
+    #[arg(short = 'm', long = "metadata", help = "Download json metadata alongside the clip")]
+    pub metadata: bool,
+
+    pub keyword: String
+}
+
+#[derive(Args, Debug)]
+pub struct ChannelsCommandArgs {
+    #[arg(short = 'o', long = "output", help = "Directory holding each broadcaster's folder, for entries without their own output [default: .]")]
+    pub output: Option<String>,
+
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
+    pub credentials: Option<String>,
+
+    #[arg(short = 'C', long = "chunk-size", visible_alias = "concurrency", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Number of clips downloaded at once, default=10")]
+    pub chunk_size: Option<usize>,
+
+    #[arg(short = 'm', long = "metadata", help = "Download json metadata alongside the clip")]
+    pub metadata: bool,
+
+    #[arg(long = "sync", help = "Skip clips already recorded in each output directory's manifest.json")]
+    pub sync: bool,
+
+    #[arg(long = "page-delay", default_value_t = 0, help = "Milliseconds to wait between pages of clips from twitch")]
+    pub page_delay: u64,
+
+    #[arg(long = "json", help = "Print a summary of every broadcaster's run to stdout as json")]
+    pub json: bool,
+
+    #[arg(help = "toml file of [[channel]] tables, or json with a \"channel\" array, each with a login and optionally output, start, end, created_after, created_before and limit")]
+    pub file: String
+}
+
+#[derive(Args, Debug)]
+pub struct UserCommandArgs {
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
+    pub credentials: Option<String>,
+
+    #[arg(short = 'i', long = "broadcaster-id", help = "Numeric broadcaster ID")]
+    pub broadcaster_id: Option<u32>,
+
+    #[arg(short = 'l', long = "broadcaster-login", help = "Broadcaster login")]
+    pub broadcaster_login: Option<String>,
+
+    #[arg(short = 'j', long = "json", help = "Print the raw user profile as json")]
+    pub json: bool
+}
+
+#[derive(Args, Debug)]
+pub struct GameCommandArgs {
+    #[arg(short = 'o', long = "output", help = "Path to directory to store the clips [default: .]")]
+    pub output: Option<String>,
+
+    #[arg(short = 'c', long = "credentials", help = "Path to a json or toml file containing client_id and client_secret, falls back to TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET")]
+    pub credentials: Option<String>,
+
+    #[arg(short = 'i', long = "game-id", help = "Numeric game/category ID")]
+    pub game_id: Option<u32>,
+
+    #[arg(short = 'g', long = "game", help = "Game/category name, as shown on twitch")]
+    pub game_name: Option<String>,
+
+    #[arg(short = 's', long = "start", help = "Start of datetime range (defaults to 1 week ago)")]
+    pub start_timestamp: Option<String>,
+
+    #[arg(short = 'e', long = "end", help = "End of datetime range, requires a start time")]
+    pub end_timestamp: Option<String>,
+
+    #[arg(long = "created-after", help = "Only keep fetched clips created after this time, the --start/--end range is still what gets fetched")]
+    pub created_after: Option<String>,
+
+    #[arg(long = "created-before", help = "Only keep fetched clips created before this time, the --start/--end range is still what gets fetched")]
+    pub created_before: Option<String>,
+
+    #[arg(short = 'n', long = "limit", help = "Only keep the N most viewed clips in the range")]
+    pub limit: Option<usize>,
+
+    #[arg(short = 'C', long = "chunk-size", visible_alias = "concurrency", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Number of clips downloaded at once, default=10")]
     pub chunk_size: Option<usize>,
 
     #[arg(short = 'L', long = "link", help = "Skip downloads and print the source file URLs to stdout")]
     pub link: bool,
 
     #[arg(short = 'm', long = "metadata", help = "Download json metadata alongside the clip")]
-    pub metadata: bool
-}
\ No newline at end of file
+    pub metadata: bool,
+
+    #[arg(long = "overwrite-metadata", requires = "metadata", help = "Rewrite metadata files that already exist instead of keeping them")]
+    pub overwrite_metadata: bool,
+
+    #[arg(long = "compact-metadata", requires = "metadata", help = "Write metadata files as compact json instead of indented")]
+    pub compact_metadata: bool,
+
+    #[arg(long = "m3u", help = "Skip downloads and write an extended M3U playlist of the source file URLs")]
+    pub m3u: Option<String>,
+
+    #[arg(long = "exec", help = "Command run after each clip downloads, with {path}, {id} and {title} filled in")]
+    pub exec: Option<String>,
+
+    #[arg(long = "exec-concurrency", default_value_t = 1, requires = "exec", help = "Maximum number of --exec commands running at once")]
+    pub exec_concurrency: usize,
+
+    #[arg(long = "sync", help = "Skip clips already recorded in the output directory's manifest.json")]
+    pub sync: bool,
+
+    #[arg(long = "since-last-run", help = "Start after the newest clip in the output directory's manifest.json, falls back to --start")]
+    pub since_last_run: bool,
+
+    #[arg(long = "failures", help = "Write clips that couldn't be downloaded to this file, as json if it ends in .json")]
+    pub failures: Option<String>,
+
+    #[arg(long = "prescan", help = "Find every clip's size before downloading to show the total, at the cost of an extra request per clip")]
+    pub prescan: bool,
+
+    #[arg(long = "checksums", conflicts_with = "link", help = "Write each clip's SHA-256 to {id}.mp4.sha256, checked by the verify command")]
+    pub checksums: bool,
+
+    #[arg(long = "thumbnails", help = "Save each clip's thumbnail as {id}.jpg next to it")]
+    pub thumbnails: bool,
+
+    #[arg(long = "embed-metadata", help = "Tag each clip with its title, creator and date, requires ffmpeg")]
+    pub embed_metadata: bool,
+
+    #[arg(long = "nfo", help = "Write a Kodi/Jellyfin {id}.nfo sidecar for each clip")]
+    pub nfo: bool,
+
+    #[arg(long = "audio-only", help = "Extract each clip's audio after downloading, requires ffmpeg")]
+    pub audio_only: bool,
+
+    #[arg(long = "audio-format", default_value = "mp3", requires = "audio_only", help = "File extension of the extracted audio, ffmpeg picks the codec from it")]
+    pub audio_format: String,
+
+    #[arg(long = "delete-video", requires = "audio_only", help = "Remove the videos once their audio was extracted")]
+    pub delete_video: bool,
+
+    #[arg(long = "concat", conflicts_with = "audio_only", help = "Join the downloaded clips, in order, into this file. Re-encodes everything, so it's slow. Requires ffmpeg")]
+    pub concat: Option<String>,
+
+    #[arg(long = "concat-only", requires = "concat", help = "Delete the individual clips once they were joined")]
+    pub concat_only: bool,
+
+    #[arg(long = "gif", help = "Make a short {id}.gif preview of each clip, skipped when ffmpeg isn't found")]
+    pub gif: bool,
+
+    #[arg(long = "gif-duration", default_value_t = 5.0, requires = "gif", value_parser = parse_offset, help = "Seconds from the start of each clip covered by its GIF")]
+    pub gif_duration: f64,
+
+    #[arg(long = "gif-width", default_value_t = 320, requires = "gif", help = "Width of the GIFs in pixels, the height keeps the aspect ratio")]
+    pub gif_width: u32,
+
+    #[arg(long = "gif-fps", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=30), requires = "gif", help = "Frame rate of the GIFs")]
+    pub gif_fps: u32,
+
+    #[arg(long = "webhook", help = "POST the run's report as json to this URL once it finishes")]
+    pub webhook: Option<String>,
+
+    #[arg(long = "webhook-header", requires = "webhook", value_parser = parse_header, help = "Extra \"Name: value\" header sent with --webhook, can be repeated")]
+    pub webhook_headers: Vec<(String, String)>,
+
+    #[arg(long = "max-downloads", help = "Refuse to download more clips than this unless --yes is passed")]
+    pub max_downloads: Option<usize>,
+
+    #[arg(short = 'y', long = "yes", help = "Go ahead with downloads over --max-downloads and skip the confirmation for large downloads")]
+    pub yes: bool,
+
+    #[arg(long = "number", help = "Prefix file names with each clip's position, e.g. 007_{id}.mp4, so they sort in download order")]
+    pub number: bool,
+
+    #[arg(long = "page-delay", default_value_t = 0, help = "Milliseconds to wait between pages of clips from twitch")]
+    pub page_delay: u64,
+
+    #[arg(long = "json", conflicts_with_all = ["link", "m3u"], help = "Print a json summary of the run to stdout, with the outcome of every clip")]
+    pub json: bool
+}
+
+#[derive(Args, Debug)]
+pub struct LoginCommandArgs {
+    #[arg(short = 'i', long = "client-id", help = "Client ID to store, prompted for if omitted")]
+    pub client_id: Option<String>
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyCommandArgs {
+    #[arg(short = 'm', long = "manifest", help = "Json array of clips (objects with an id, and optionally the path they were saved to) to check, instead of the directory's manifest.json or every .mp4 in it")]
+    pub manifest: Option<String>,
+
+    #[arg(short = 'p', long = "probe", help = "Also check each file starts with an mp4 header")]
+    pub probe: bool,
+
+    #[arg(short = 'C', long = "chunk-size", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Number of clips checked at once, default=10")]
+    pub chunk_size: Option<usize>,
+
+    #[arg(default_value = ".", help = "Directory containing the downloaded clips")]
+    pub directory: String
+}
+
+#[derive(Args, Debug)]
+pub struct CompletionsCommandArgs {
+    #[arg(help = "Shell to generate the script for")]
+    pub shell: Shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_since_unit() {
+        assert_eq!(parse_since("45s"), Ok(TimeDelta::seconds(45)));
+        assert_eq!(parse_since("30m"), Ok(TimeDelta::minutes(30)));
+        assert_eq!(parse_since("12h"), Ok(TimeDelta::hours(12)));
+        assert_eq!(parse_since("7d"), Ok(TimeDelta::days(7)));
+        assert_eq!(parse_since("2w"), Ok(TimeDelta::weeks(2)));
+    }
+
+    #[test]
+    fn rejects_zero_concurrency() {
+        for args in [vec!["twdl", "channel", "-C", "0"], vec!["twdl", "search", "--concurrency", "0", "clutch"], vec!["twdl", "verify", "-C", "0"]] {
+            assert!(Cli::try_parse_from(&args).is_err(), "{args:?}");
+        }
+        let args = Cli::try_parse_from(["twdl", "channel", "-C", "1"]).unwrap();
+        assert!(matches!(args.command, Commands::Channel(ChannelCommandArgs { chunk_size: Some(1), .. })));
+    }
+
+    #[test]
+    fn rejects_invalid_since() {
+        for value in ["", "7", "d", "0d", "-1d", "7y", "1.5h", "7 d", "99999999999999999999w"] {
+            assert!(parse_since(value).is_err(), "{value}");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{build_http_client, error::Result, HttpOptions};
+
+// One client per proxy, handed out in turn so concurrent downloads spread over every proxy.
+// Each client keeps its own connections, so a download only reuses connections opened through the same proxy.
+
+#[derive(Debug)]
+pub struct ClientPool {
+    clients: Vec<reqwest::Client>,
+
+    next: AtomicUsize
+}
+
+impl ClientPool {
+    // Builds a client for every proxy in `proxies` with the rest of `options`
+    pub fn with_proxies(proxies: &[String], options: &HttpOptions) -> Result<Self> {
+        let clients = proxies.iter()
+            .map(|proxy| build_http_client(&HttpOptions { proxy: Some(proxy.clone()), ..options.clone() }))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ClientPool { clients, next: AtomicUsize::new(0) })
+    }
+
+    // None when the pool is empty
+    pub fn next(&self) -> Option<&reqwest::Client> {
+        if self.clients.is_empty() {
+            return None;
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+        self.clients.get(i)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
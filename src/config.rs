@@ -0,0 +1,38 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::error::{Result, TwdlError};
+
+// Defaults read from the user's config file, CLI flags and env vars take precedence over these
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub credentials: Option<String>,
+
+    pub output: Option<String>,
+
+    pub concurrency: Option<usize>,
+
+    pub retries: Option<u32>,
+
+    pub quality: Option<u32>
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("twdl").join("config.toml"))
+}
+
+// A missing config file is not an error, it just means no defaults are set
+pub async fn load_config() -> Result<Config> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+    match fs::read_to_string(&path).await {
+        Ok(contents) => Ok(toml::from_str(&contents)?),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Config::default()),
+        Err(err) => Err(TwdlError::io(format!("Failed to read config {}", path.display()), err))
+    }
+}
@@ -0,0 +1,179 @@
+use std::{path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::future::BoxFuture;
+use indicatif::MultiProgress;
+use tokio::sync::mpsc::Sender;
+use twitch_api::{helix::clips::Clip, types::UserId};
+
+use crate::{
+    build_http_client, clip_callback, clip_path, download_clips, download_source, extract_clip_slug, get_video_source_files, get_video_source_info, save_metadata, select_source_file,
+    error::{Result, TwdlError},
+    events::DownloadEvent,
+    report::DownloadReport,
+    twitch_utils::{get_clip, get_clips_chunked, ClipSource, DateChunkingType, TwitchSession},
+    ClipCallback, DownloadOptions, GqlConfig, HttpOptions, SourceFile, SourceInfo
+};
+
+// Library entry point bundling the shared client, twitch session and download settings.
+//
+//     let downloader = Downloader::builder()
+//         .output_dir("clips")
+//         .quality(720)
+//         .build()?;
+//     downloader.download_clip_by_url("https://clips.twitch.tv/SomeSlug").await?;
+pub struct Downloader {
+    client: reqwest::Client,
+
+    // Only needed for helix lookups, clips can be resolved and downloaded without one
+    session: Option<Arc<TwitchSession>>,
+
+    gql: GqlConfig,
+
+    options: DownloadOptions,
+
+    output_dir: PathBuf,
+
+    progress: Option<Arc<MultiProgress>>,
+
+    on_complete: Option<ClipCallback>
+}
+
+#[derive(Default)]
+pub struct DownloaderBuilder {
+    client: Option<reqwest::Client>,
+    http: HttpOptions,
+    session: Option<Arc<TwitchSession>>,
+    gql: GqlConfig,
+    options: DownloadOptions,
+    output_dir: Option<PathBuf>,
+    progress: Option<Arc<MultiProgress>>,
+    on_complete: Option<ClipCallback>
+}
+
+impl DownloaderBuilder {
+    // Use an existing client instead of building one from the http options
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn http_options(mut self, http: HttpOptions) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn session(mut self, session: Arc<TwitchSession>) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    pub fn gql(mut self, gql: GqlConfig) -> Self {
+        self.gql = gql;
+        self
+    }
+
+    pub fn options(mut self, options: DownloadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.options.concurrency = concurrency;
+        self
+    }
+
+    pub fn quality(mut self, quality: u32) -> Self {
+        self.options.quality = Some(quality);
+        self
+    }
+
+    pub fn events(mut self, events: Sender<DownloadEvent>) -> Self {
+        self.options.events = Some(events);
+        self
+    }
+
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    // Draw channel download progress under this, nothing is drawn when unset
+    pub fn progress(mut self, progress: Arc<MultiProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    // Run after each clip of a channel download succeeds, see `download_clips` for ordering
+    pub fn on_complete<F>(mut self, callback: F) -> Self
+    where
+        F: for<'a> Fn(&'a Clip, &'a Path) -> BoxFuture<'a, ()> + Send + Sync + 'static
+    {
+        self.on_complete = Some(clip_callback(callback));
+        self
+    }
+
+    pub fn build(self) -> Result<Downloader> {
+        let client = match self.client {
+            Some(client) => client,
+            None => build_http_client(&self.http)?
+        };
+        Ok(Downloader {
+            client,
+            session: self.session,
+            gql: self.gql,
+            options: self.options,
+            output_dir: self.output_dir.unwrap_or_else(|| PathBuf::from(".")),
+            progress: self.progress,
+            on_complete: self.on_complete
+        })
+    }
+}
+
+impl Downloader {
+    pub fn builder() -> DownloaderBuilder {
+        DownloaderBuilder::default()
+    }
+
+    pub async fn resolve_source_files(&self, slug: &str) -> Result<Vec<SourceFile>> {
+        get_video_source_files(&self.client, slug, &self.gql).await
+    }
+
+    pub async fn resolve_source_info(&self, slug: &str) -> Result<SourceInfo> {
+        get_video_source_info(&self.client, slug, &self.gql).await
+    }
+
+    // Downloads a single clip from its URL or slug, returning where it was written
+    pub async fn download_clip_by_url(&self, url: &str) -> Result<PathBuf> {
+        let slug = extract_clip_slug(url).ok_or_else(|| TwdlError::InvalidSlug(url.to_string()))?;
+        let files = self.resolve_source_files(&slug).await?;
+        let source = select_source_file(&files, self.options.quality)
+            .ok_or_else(|| TwdlError::NoSourceFiles(slug.clone()))?;
+
+        let clip = match self.options.metadata {
+            true => get_clip(&slug, self.require_session()?).await?,
+            false => None
+        };
+        if let Some(clip) = clip {
+            save_metadata(&clip, Some(source), &self.output_dir, &self.options).await;
+        }
+
+        let path = clip_path(&self.output_dir, &slug);
+        download_source(&self.client, &slug, source, &path, &self.gql, &self.options).await?;
+        Ok(path)
+    }
+
+    // Downloads every clip a broadcaster has between `start` and `end`
+    pub async fn download_channel(&self, broadcaster_id: &UserId, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<DownloadReport> {
+        let session = self.require_session()?;
+        let source = ClipSource::Broadcaster(broadcaster_id.clone());
+        let chunking_type = DateChunkingType::ByDuration(TimeDelta::weeks(4));
+        let clips = get_clips_chunked(&source, session, start, end, chunking_type, Some(100), Duration::ZERO).await;
+        Ok(download_clips(self.progress.clone(), &self.client, clips, &self.output_dir, &self.gql, &self.options, self.on_complete.as_ref()).await)
+    }
+
+    fn require_session(&self) -> Result<&TwitchSession> {
+        self.session.as_deref()
+            .ok_or_else(|| TwdlError::Auth("this needs a twitch session, set one with DownloaderBuilder::session".to_string()))
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use twitch_api::helix::ClientRequestError;
+
+// Errors returned across the library boundary, so callers can tell failure modes apart
+
+#[derive(Debug, Error)]
+pub enum TwdlError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("GQL request failed: {0}")]
+    Gql(String),
+
+    #[error("Failed to parse response: {0}")]
+    Deserialize(#[from] serde_json::Error),
+
+    // Config and batch files
+    #[error("Failed to parse toml: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("No source files found for clip {0}")]
+    NoSourceFiles(String),
+
+    // GQL answers with a null clip for unknown slugs as well as deleted, subscriber-only or region-locked clips
+    #[error("Clip {slug} is unavailable{}", .reason.as_ref().map(|reason| format!(" ({reason})")).unwrap_or_default())]
+    ClipUnavailable {
+        slug: String,
+
+        // Messages from the response's `errors`, when there were any
+        reason: Option<String>
+    },
+
+    #[error("Clip {0} has no available qualities, it may be deleted or still processing")]
+    NoQualities(String),
+
+    #[error("Clip {slug} is only available up to {best}p, below the minimum of {min}p")]
+    BelowMinQuality {
+        slug: String,
+
+        best: u32,
+
+        min: u32
+    },
+
+    #[error("Authentication failed: {0}")]
+    Auth(String),
+
+    #[error("Invalid clip slug: {0}")]
+    InvalidSlug(String),
+
+    #[error("Helix request failed: {0}")]
+    Helix(#[from] ClientRequestError<reqwest::Error>),
+
+    #[error("Invalid proxy: {0}")]
+    InvalidProxy(String),
+
+    #[error("Response for clip {0} is not a video: {1}")]
+    NotVideo(String, String),
+
+    #[error("Download of clip {0} ended after {2} of {1} bytes")]
+    Incomplete(String, u64, u64),
+
+    #[error("Ranged download failed: {0}")]
+    Range(String),
+
+    #[error("ffmpeg failed: {0}")]
+    Ffmpeg(String),
+
+    #[error("Download timed out after {}s", .0.as_secs())]
+    Timeout(Duration),
+
+    #[error("Resolving the source of clip {0} timed out after {}s", .1.as_secs())]
+    ResolveTimeout(String, Duration),
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+
+        source: std::io::Error
+    }
+}
+
+impl TwdlError {
+    // Twitch answers an expired playback token in a source URL with 403
+    pub fn is_forbidden(&self) -> bool {
+        matches!(self, TwdlError::Http(err) if err.status() == Some(reqwest::StatusCode::FORBIDDEN))
+    }
+
+    pub(crate) fn io(context: impl Into<String>, source: std::io::Error) -> Self {
+        TwdlError::Io { context: context.into(), source }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, TwdlError>;
@@ -0,0 +1,36 @@
+use std::{io::Write, path::PathBuf};
+
+use log::warn;
+use serde::Serialize;
+use tokio::sync::mpsc::Receiver;
+
+// Progress notifications for embedding the downloader in other UIs
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DownloadEvent {
+    // The server answered, `total_bytes` is its Content-Length if it sent one
+    Started { clip_id: String, total_bytes: Option<u64> },
+
+    // `bytes` is the running total written so far
+    Progress { clip_id: String, bytes: u64 },
+
+    Completed { clip_id: String, path: PathBuf },
+
+    Failed { clip_id: String, error: String }
+}
+
+// Writes each event as one json line until every sender is dropped. Lines are flushed as
+// they're written so a reader sees progress as it happens.
+pub async fn write_json_lines(mut output: Box<dyn Write + Send>, mut events: Receiver<DownloadEvent>) {
+    while let Some(event) = events.recv().await {
+        let written = serde_json::to_string(&event)
+            .map_err(std::io::Error::from)
+            .and_then(|line| writeln!(output, "{line}"))
+            .and_then(|()| output.flush());
+        if let Err(err) = written {
+            warn!("Stopped writing progress events: {err}");
+            return;
+        }
+    }
+}
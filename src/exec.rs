@@ -0,0 +1,72 @@
+use std::{path::Path, sync::Mutex};
+
+use log::{debug, error};
+use tokio::{process::Command, sync::Semaphore};
+use twitch_api::helix::clips::Clip;
+
+use crate::report::ExecFailure;
+
+// Runs a user supplied command after each clip downloads.
+// The template is split on whitespace and placeholders are filled in per argument,
+// so titles containing spaces or quotes stay a single argument and no shell is involved.
+
+pub struct ExecHook {
+    template: String,
+
+    permits: Semaphore,
+
+    // Since the last `take_failures`
+    failures: Mutex<Vec<ExecFailure>>
+}
+
+impl ExecHook {
+    pub fn new(template: String, concurrency: usize) -> Self {
+        ExecHook {
+            template,
+            permits: Semaphore::new(concurrency.max(1)),
+            failures: Mutex::new(Vec::new())
+        }
+    }
+
+    pub async fn run(&self, clip: &Clip, path: &Path) {
+        let args = render_template(&self.template, clip, path);
+        let Some((program, args)) = args.split_first() else {
+            return;
+        };
+
+        // The semaphore is never closed, acquiring can't fail
+        let _permit = self.permits.acquire().await.unwrap();
+        match Command::new(program).args(args).status().await {
+            Ok(status) if status.success() => debug!("--exec finished for clip {}", clip.id),
+            Ok(status) => {
+                error!("--exec for clip {} exited with {status}", clip.id);
+                self.fail(clip, status.code(), format!("exited with {status}"));
+            }
+            Err(err) => {
+                error!("Failed to run --exec for clip {}: {err}", clip.id);
+                self.fail(clip, None, err.to_string());
+            }
+        }
+    }
+
+    fn fail(&self, clip: &Clip, exit_code: Option<i32>, error: String) {
+        self.failures.lock().unwrap().push(ExecFailure { clip_id: clip.id.to_string(), exit_code, error });
+    }
+
+    // Commands that couldn't be started or exited non-zero since this was last called,
+    // so a hook shared by several runs can hand each report its own
+    pub fn take_failures(&self) -> Vec<ExecFailure> {
+        std::mem::take(&mut *self.failures.lock().unwrap())
+    }
+}
+
+// Supports {path}, {id} and {title}
+pub fn render_template(template: &str, clip: &Clip, path: &Path) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| arg
+            .replace("{path}", &path.to_string_lossy())
+            .replace("{id}", clip.id.as_str())
+            .replace("{title}", &clip.title))
+        .collect()
+}
@@ -0,0 +1,171 @@
+use std::{path::{Path, PathBuf}, process::Stdio};
+
+use tokio::{fs, process::Command};
+use twitch_api::helix::clips::Clip;
+
+use crate::error::{Result, TwdlError};
+
+// Post-processing of downloaded clips through an ffmpeg found on PATH
+
+pub async fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+// Runs ffmpeg with `args`, turning a non-zero exit into an error carrying its stderr
+async fn run(args: &[&str]) -> Result<()> {
+    let output = Command::new("ffmpeg")
+        .args(["-y", "-v", "error"])
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|err| TwdlError::Ffmpeg(format!("failed to start: {err}")))?;
+    if !output.status.success() {
+        return Err(TwdlError::Ffmpeg(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+// `{path}.part`, the same name unfinished downloads use
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+// Remuxes `path` in place with the clip's title, creator and creation date as container tags
+pub async fn embed_metadata(clip: &Clip, path: &Path) -> Result<()> {
+    let part = part_path(path);
+    let input = path.to_string_lossy();
+    let output = part.to_string_lossy();
+    let title = format!("title={}", clip.title);
+    let artist = format!("artist={}", clip.creator_name);
+    // created_at is RFC 3339, the date tag only wants the day
+    let date = format!("date={}", clip.created_at.as_str().get(..10).unwrap_or(clip.created_at.as_str()));
+
+    let result = run(&[
+        "-i", &input,
+        "-map", "0", "-c", "copy",
+        "-metadata", &title,
+        "-metadata", &artist,
+        "-metadata", &date,
+        "-f", "mp4", &output
+    ]).await;
+    if let Err(err) = result {
+        let _ = fs::remove_file(&part).await;
+        return Err(err);
+    }
+    fs::rename(&part, path).await
+        .map_err(|err| TwdlError::io(format!("Failed to rename {} to {}", part.display(), path.display()), err))
+}
+
+// Writes the audio track of `path` next to it as `{stem}.{format}`, ffmpeg picks the codec from the extension
+pub async fn extract_audio(path: &Path, format: &str) -> Result<PathBuf> {
+    let audio = path.with_extension(format);
+    let input = path.to_string_lossy();
+    let output = audio.to_string_lossy();
+
+    if let Err(err) = run(&["-i", &input, "-vn", &output]).await {
+        let _ = fs::remove_file(&audio).await;
+        return Err(err);
+    }
+    Ok(audio)
+}
+
+// Writes the part of `path` between `start` and `end` seconds to `output`.
+// Re-encodes so the cut is frame accurate, stream copying could only cut on keyframes.
+pub async fn trim(path: &Path, output: &Path, start: f64, end: Option<f64>) -> Result<()> {
+    let input = path.to_string_lossy();
+    let output_name = output.to_string_lossy();
+    let start = start.to_string();
+    let end = end.map(|end| end.to_string());
+
+    let mut args: Vec<&str> = vec!["-i", &input, "-ss", &start];
+    if let Some(end) = &end {
+        args.extend(["-to", end.as_str()]);
+    }
+    args.push(&output_name);
+    if let Err(err) = run(&args).await {
+        let _ = fs::remove_file(output).await;
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct GifOptions {
+    // Seconds from the start of the clip
+    pub duration: f64,
+
+    pub width: u32,
+
+    pub fps: u32
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions { duration: 5.0, width: 320, fps: 10 }
+    }
+}
+
+// Writes the start of `path` to `output` as a looping GIF.
+// A palette is generated from the clip itself, the default one bands badly on stream footage.
+pub async fn gif(path: &Path, output: &Path, options: &GifOptions) -> Result<()> {
+    let input = path.to_string_lossy();
+    let output_name = output.to_string_lossy();
+    let duration = options.duration.to_string();
+    let filter = format!(
+        "fps={},scale={}:-1:flags=lanczos,split[a][b];[a]palettegen[p];[b][p]paletteuse",
+        options.fps, options.width
+    );
+
+    if let Err(err) = run(&["-t", &duration, "-i", &input, "-vf", &filter, "-loop", "0", &output_name]).await {
+        let _ = fs::remove_file(output).await;
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Every clip is scaled and padded to this before joining, since clips can differ in resolution
+const CONCAT_WIDTH: u32 = 1920;
+const CONCAT_HEIGHT: u32 = 1080;
+
+// Joins `files` in order into `output` with the concat demuxer.
+// Everything gets re-encoded to one resolution, which takes roughly as long as the clips play for,
+// far longer than the downloads themselves.
+pub async fn concat(files: &[PathBuf], output: &Path) -> Result<()> {
+    let mut list = String::new();
+    for file in files {
+        let file = fs::canonicalize(file).await
+            .map_err(|err| TwdlError::io(format!("Failed to find {}", file.display()), err))?;
+        // Single quotes are escaped by closing the quote, escaping one and reopening it
+        list.push_str(&format!("file '{}'\n", file.to_string_lossy().replace('\'', "'\\''")));
+    }
+    let list_path = output.with_extension("concat.txt");
+    fs::write(&list_path, list).await
+        .map_err(|err| TwdlError::io(format!("Failed to write {}", list_path.display()), err))?;
+
+    let list_name = list_path.to_string_lossy();
+    let output_name = output.to_string_lossy();
+    let filter = format!(
+        "scale={CONCAT_WIDTH}:{CONCAT_HEIGHT}:force_original_aspect_ratio=decrease,pad={CONCAT_WIDTH}:{CONCAT_HEIGHT}:(ow-iw)/2:(oh-ih)/2,setsar=1"
+    );
+    let result = run(&[
+        "-f", "concat", "-safe", "0", "-i", &list_name,
+        "-vf", &filter,
+        "-c:v", "libx264", "-preset", "veryfast", "-crf", "20",
+        "-c:a", "aac", "-ar", "48000",
+        &output_name
+    ]).await;
+    let _ = fs::remove_file(&list_path).await;
+    if result.is_err() {
+        let _ = fs::remove_file(output).await;
+    }
+    result
+}
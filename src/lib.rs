@@ -1,20 +1,261 @@
 pub mod twitch_utils;
+pub mod batch;
+pub mod checksum;
 pub mod cli;
+pub mod client_pool;
+pub mod config;
+pub mod downloader;
+pub mod error;
+pub mod events;
+pub mod exec;
+pub mod ffmpeg;
+pub mod manifest;
+pub mod nfo;
+pub mod playlist;
+pub mod prescan;
+pub mod progress;
+pub mod rate_limit;
+mod split;
+mod private_file;
+pub mod report;
+pub mod retry_budget;
+pub mod source_cache;
+pub mod token_cache;
+pub mod user_cache;
+pub mod verify;
+pub mod webhook;
 
-use log::{error, debug};
+use log::{error, debug, info, warn};
 
-use std::{fmt::Display, path::{Path, PathBuf}, str::FromStr, sync::Arc};
-use anyhow::Result;
+use std::{collections::HashMap, fmt::Display, path::{Path, PathBuf}, str::FromStr, sync::Arc, time::{Duration, Instant}};
+use error::{Result, TwdlError};
 use indicatif::{MultiProgress, ProgressBar};
 mod video_source_response;
-use futures_util::{future::join_all, StreamExt};
+use futures_util::{future::{join_all, BoxFuture}, stream, StreamExt};
 use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
-use reqwest::Url;
-use tokio::{fs::{self, File}, io::AsyncWriteExt};
+use regex::Regex;
+use reqwest::{header::{CONTENT_LENGTH, CONTENT_TYPE}, Url};
+use tokio::{fs::{self, File}, io::{AsyncWrite, AsyncWriteExt}, sync::mpsc::Sender, time::{sleep, timeout}};
 use twitch_api::helix::clips::Clip;
-use video_source_response::VideoSourceResponse;
+use video_source_response::{ErrorResponse, VideoSourceResponse};
+use rate_limit::RateLimiter;
+use retry_budget::RetryBudget;
+use source_cache::SourceCache;
+use events::DownloadEvent;
+use checksum::{hash_file, sidecar_path, write_sidecar, HashingWriter};
+use client_pool::ClientPool;
+use prescan::{prescan_clips, Prescan};
+use progress::{byte_progress_bar, clip_bar, ByteBar, ByteProgress};
+use split::{download_split, range_length};
+use manifest::{Manifest, ManifestEntry};
+use report::{bytes_per_second, DownloadReport, DownloadedClip, FailedClip, SkippedClip};
 
-#[derive(PartialEq, Eq, Debug)]
+// Public Client-ID used by the twitch web player
+pub const DEFAULT_GQL_CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+
+pub const DEFAULT_GQL_ENDPOINT: &str = "https://gql.twitch.tv/gql";
+
+// Settings for the GQL endpoint used to resolve clip source files
+#[derive(Clone, Debug)]
+pub struct GqlConfig {
+    pub client_id: String,
+
+    pub endpoint: String,
+
+    // Reused by everything resolving through this config, nothing is cached when None
+    pub cache: Option<Arc<SourceCache>>,
+
+    // Limit on each source lookup, separate from `DownloadOptions::timeout`
+    pub resolve_timeout: Option<Duration>
+}
+
+impl Default for GqlConfig {
+    fn default() -> Self {
+        GqlConfig {
+            client_id: DEFAULT_GQL_CLIENT_ID.to_string(),
+            endpoint: DEFAULT_GQL_ENDPOINT.to_string(),
+            cache: None,
+            resolve_timeout: None
+        }
+    }
+}
+
+pub const DEFAULT_USER_AGENT: &str = concat!("twdl/", env!("CARGO_PKG_VERSION"));
+
+// Options for the HTTP client shared by GQL, helix and download requests
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    // http(s):// or socks5(h):// URL, reqwest still honours HTTPS_PROXY when unset
+    pub proxy: Option<String>,
+
+    pub user_agent: String,
+
+    pub connect_timeout: Option<Duration>
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            proxy: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            connect_timeout: None
+        }
+    }
+}
+
+pub fn build_http_client(options: &HttpOptions) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(&options.user_agent);
+    if let Some(connect_timeout) = options.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if let Some(proxy) = &options.proxy {
+        let url = Url::parse(proxy).map_err(|err| TwdlError::InvalidProxy(format!("{proxy} ({err})")))?;
+        if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+            return Err(TwdlError::InvalidProxy(format!("unsupported scheme {}", url.scheme())));
+        }
+        builder = builder.proxy(reqwest::Proxy::all(url)?);
+    }
+    Ok(builder.build()?)
+}
+
+// How clips and their files get downloaded
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    // Number of clips downloaded at once
+    pub concurrency: usize,
+
+    pub metadata: bool,
+
+    // Rewrite `{id}_metadata.json` files that already exist, they're kept otherwise
+    pub overwrite_metadata: bool,
+
+    // Indent metadata files for reading, compact ones are smaller
+    pub pretty_metadata: bool,
+
+    // Limit on a single file download, including the time spent streaming
+    pub timeout: Option<Duration>,
+
+    // Extra attempts made after a failed download
+    pub retries: u32,
+
+    // Passes made over the clips that still failed once everything else was attempted
+    pub retry_passes: u32,
+
+    // Caps `retries` across every download sharing these options, retry passes stop once it runs out
+    pub retry_budget: Option<Arc<RetryBudget>>,
+
+    // Preferred vertical resolution, the best available is used when it isn't offered
+    pub quality: Option<u32>,
+
+    // Clips whose best file is below this resolution are skipped instead of downloaded
+    pub min_quality: Option<u32>,
+
+    // Clip files are downloaded through these in turn instead of the shared client, GQL and helix requests aren't
+    pub client_pool: Option<Arc<ClientPool>>,
+
+    // Shared between all downloads so the limit applies to their total rate
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+
+    // Receives progress for every clip, for consumers that draw their own UI
+    pub events: Option<Sender<DownloadEvent>>,
+
+    // Advanced by every download sharing these options, `download_clips` adds one when drawing progress
+    pub byte_bar: Option<ByteBar>,
+
+    // Write a `{file}.sha256` next to each downloaded clip
+    pub checksums: bool,
+
+    // Save each clip's thumbnail as `{id}.jpg` next to it
+    pub thumbnails: bool,
+
+    // Write a Kodi/Jellyfin `{id}.nfo` sidecar for each clip
+    pub nfo: bool,
+
+    // Tag each clip with its title, creator and date through ffmpeg, which must be on PATH
+    pub embed_metadata: bool,
+
+    // Extract each clip's audio to this format (e.g. mp3) through ffmpeg
+    pub audio_format: Option<String>,
+
+    // Write a short `{id}.gif` preview of each clip through ffmpeg
+    pub gif: Option<ffmpeg::GifOptions>,
+
+    // Remove the video once its audio was extracted
+    pub delete_video: bool,
+
+    // Connections used for a single file when the server supports byte ranges, 1 downloads it in one stream
+    pub split: u32,
+
+    // Prefix file names with the clip's zero-padded position, e.g. `007_{id}.mp4`, so they sort in the given order
+    pub number: bool,
+
+    // Resolve and size every clip before `download_clips` starts downloading, see `prescan`
+    pub prescan: bool,
+
+    // Skip clips already recorded in the output directory's manifest
+    pub sync: bool
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            concurrency: 10,
+            metadata: false,
+            overwrite_metadata: false,
+            pretty_metadata: true,
+            timeout: None,
+            retries: 2,
+            retry_passes: 1,
+            quality: None,
+            min_quality: None,
+            client_pool: None,
+            rate_limiter: None,
+            retry_budget: None,
+            events: None,
+            byte_bar: None,
+            checksums: false,
+            thumbnails: false,
+            nfo: false,
+            audio_format: None,
+            delete_video: false,
+            embed_metadata: false,
+            gif: None,
+            split: 1,
+            number: false,
+            prescan: false,
+            sync: false
+        }
+    }
+}
+
+// Async hook run after each clip downloads successfully, e.g. `clip_callback(|clip, path| Box::pin(async move { ... }))`
+pub type ClipCallback = Arc<dyn for<'a> Fn(&'a Clip, &'a Path) -> BoxFuture<'a, ()> + Send + Sync>;
+
+// Closures only get the higher-ranked signature when it's spelled out as a bound
+pub fn clip_callback<F>(callback: F) -> ClipCallback
+where
+    F: for<'a> Fn(&'a Clip, &'a Path) -> BoxFuture<'a, ()> + Send + Sync + 'static
+{
+    Arc::new(callback)
+}
+
+const MP4_HEADER_LEN: usize = 8;
+
+// MP4 files open with a box whose type, at bytes 4..8, is `ftyp`
+pub fn looks_like_mp4(header: &[u8]) -> bool {
+    header.get(4..8) == Some(b"ftyp".as_slice())
+}
+
+// Downloads are rejected unless they look like mp4, so every clip file gets this extension
+pub const CLIP_EXTENSION: &str = "mp4";
+
+// Where a clip named `stem` is written in `directory`
+pub fn clip_path(directory: &Path, stem: &str) -> PathBuf {
+    directory.join(format!("{stem}.{CLIP_EXTENSION}"))
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct SourceFile {
     pub quality: u32,
     pub frame_rate: u32,
@@ -39,17 +280,60 @@ impl Display for SourceFile {
     }
 }
 
-fn format_source_urls(response: &VideoSourceResponse) -> Result<Vec<SourceFile>> {
-    let sig = &response.data.clip.playback_access_token.signature;
-    let token = &response.data.clip.playback_access_token.value;
+// Picks the highest frame rate file at `quality` if there is one, otherwise the best file overall
+pub fn select_source_file(files: &[SourceFile], quality: Option<u32>) -> Option<&SourceFile> {
+    let preferred = quality.and_then(|quality| files.iter()
+        .filter(|file| file.quality == quality)
+        .max_by_key(|file| file.frame_rate));
+    preferred.or_else(|| files.iter().max())
+}
+
+// Errors when `min_quality` is set and none of `files` reach it
+pub fn check_min_quality(files: &[SourceFile], min_quality: Option<u32>, clip_slug: &str) -> Result<()> {
+    let Some(min) = min_quality else {
+        return Ok(());
+    };
+    let best = files.iter().map(|file| file.quality).max().unwrap_or(0);
+    match best < min {
+        true => Err(TwdlError::BelowMinQuality { slug: clip_slug.to_string(), best, min }),
+        false => Ok(())
+    }
+}
+
+// Pulls the clip slug out of a clip URL, or returns the input if it is already a bare slug
+pub fn extract_clip_slug(input: &str) -> Option<String> {
+    let re = Regex::new(r"(?:https?://(?:www\.)?twitch\.tv/[^/]+/clip/|https?://clips\.twitch\.tv/)?([A-Za-z0-9_-]+)")
+        .expect("Failed to parse regex string");
+
+    re.captures(input)
+        .and_then(|caps| caps.get(1))
+        .map(|m| m.as_str().to_string())
+}
+
+// "720", "720p" and "720p60" give their height. Labels without a number, like audio_only, sort below every video as 0.
+fn parse_quality(label: &str) -> Option<u32> {
+    if !label.chars().any(|c| c.is_ascii_digit()) {
+        return Some(0);
+    }
+    label.split_once('p').map_or(label, |(height, _)| height).parse().ok()
+}
+
+fn format_source_urls(clip: &video_source_response::Clip) -> Result<Vec<SourceFile>> {
+    let sig = &clip.playback_access_token.signature;
+    let token = &clip.playback_access_token.value;
     let encoded_token = percent_encode(token.as_bytes(), NON_ALPHANUMERIC);
     let mut output: Vec<SourceFile> = Vec::new();
-    for quality in &response.data.clip.video_qualities {
+    for quality in &clip.video_qualities {
+        let Some(height) = parse_quality(&quality.quality) else {
+            warn!("Skipping source file with unexpected quality {}", quality.quality);
+            continue;
+        };
         let url = quality.source_url.clone();
         let source_file = SourceFile{
-            quality: quality.quality.parse::<u32>()?,
+            quality: height,
             frame_rate: quality.frame_rate.round() as u32,
-            url: Url::from_str(&format!("{url}?sig={sig}&token={encoded_token}"))?,
+            url: Url::from_str(&format!("{url}?sig={sig}&token={encoded_token}"))
+                .map_err(|err| TwdlError::Gql(format!("Invalid source URL {url} ({err})")))?,
         };
         output.push(source_file);
     }
@@ -57,13 +341,28 @@ fn format_source_urls(response: &VideoSourceResponse) -> Result<Vec<SourceFile>>
     Ok(output)
 }
 
-async fn request_video_source_info(clip_slug: &String) -> Result<String> {
-    let client = reqwest::Client::builder()
-    .build()?;
+// Sends the GQL source lookup and returns the raw response body. Implemented for reqwest::Client,
+// other implementations can stand in for twitch, e.g. to replay saved responses.
+pub trait SourceFetcher: Sync {
+    fn fetch_source_info<'a>(&'a self, clip_slug: &'a str, gql: &'a GqlConfig) -> BoxFuture<'a, Result<String>>;
+}
+
+impl SourceFetcher for reqwest::Client {
+    fn fetch_source_info<'a>(&'a self, clip_slug: &'a str, gql: &'a GqlConfig) -> BoxFuture<'a, Result<String>> {
+        Box::pin(request_video_source_info(self, clip_slug, gql))
+    }
+}
+
+async fn request_video_source_info(client: &reqwest::Client, clip_slug: &str, gql: &GqlConfig) -> Result<String> {
+    // The slug is spliced into the query below, anything else would break out of the string
+    if clip_slug.is_empty() || !clip_slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(TwdlError::InvalidSlug(clip_slug.to_string()));
+    }
 
     let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("Client-ID", "kimne78kx3ncx6brgo4mv6wki5h1ko".parse()?);
-    headers.insert("Content-Type", "application/json".parse()?);
+    headers.insert("Client-ID", gql.client_id.parse()
+        .map_err(|_| TwdlError::Gql(format!("Invalid Client-ID {}", gql.client_id)))?);
+    headers.insert("Content-Type", reqwest::header::HeaderValue::from_static("application/json"));
 
     let data = format!(
     r#"{{
@@ -71,13 +370,13 @@ async fn request_video_source_info(clip_slug: &String) -> Result<String> {
     "variables": {{
         "slug": "{clip_slug}"
     }},
-    "query": "query VideoAccessToken_Clip($slug: ID!) {{ clip(slug: $slug) {{ playbackAccessToken(params: {{platform: \"web\", playerBackend: \"mediaplayer\", playerType: \"site\"}}) {{ signature value }} videoQualities {{ quality frameRate sourceURL }} }} }}"
+    "query": "query VideoAccessToken_Clip($slug: ID!) {{ clip(slug: $slug) {{ playbackAccessToken(params: {{platform: \"web\", playerBackend: \"mediaplayer\", playerType: \"site\"}}) {{ signature value }} durationSeconds videoQualities {{ quality frameRate sourceURL }} }} }}"
     }}"#
     );
 
     let json: serde_json::Value = serde_json::from_str(&data)?;
 
-    let request = client.request(reqwest::Method::POST, "https://gql.twitch.tv/gql")
+    let request = client.request(reqwest::Method::POST, &gql.endpoint)
         .headers(headers)
         .json(&json);
 
@@ -86,112 +385,741 @@ async fn request_video_source_info(clip_slug: &String) -> Result<String> {
     Ok(body)
 }
 
-pub async fn get_video_source_files(clip_slug: &String) -> Result<Vec<SourceFile>> {
-    let body = request_video_source_info(clip_slug).await?;
+fn check_gql_errors(body: &str, clip_slug: &str) -> Result<()> {
+    // Anything that doesn't fit is left for the full parse to report
+    let Ok(response) = serde_json::from_str::<ErrorResponse>(body) else {
+        return Ok(());
+    };
+    let messages: Vec<&str> = response.errors.iter().map(|error| error.message.as_str()).collect();
+    let reason = (!messages.is_empty()).then(|| messages.join(", "));
+    match (response.data, reason) {
+        (Some(data), reason) if data.clip.is_none() => Err(TwdlError::ClipUnavailable { slug: clip_slug.to_string(), reason }),
+        (_, Some(reason)) => Err(TwdlError::Gql(reason)),
+        _ => Ok(())
+    }
+}
+
+// Everything the GQL lookup tells about a clip's playback
+#[derive(Debug)]
+pub struct SourceInfo {
+    // Missing when twitch doesn't report it
+    pub duration_ms: Option<u64>,
+
+    pub files: Vec<SourceFile>
+}
+
+pub async fn get_video_source_files<F: SourceFetcher + ?Sized>(fetcher: &F, clip_slug: &str, gql: &GqlConfig) -> Result<Vec<SourceFile>> {
+    Ok(get_video_source_info(fetcher, clip_slug, gql).await?.files)
+}
+
+pub async fn get_video_source_info<F: SourceFetcher + ?Sized>(fetcher: &F, clip_slug: &str, gql: &GqlConfig) -> Result<SourceInfo> {
+    if let Some(info) = gql.cache.as_ref().and_then(|cache| cache.get(clip_slug)) {
+        return Ok(info);
+    }
+
+    let body = match gql.resolve_timeout {
+        Some(limit) => timeout(limit, fetcher.fetch_source_info(clip_slug, gql)).await
+            .map_err(|_| TwdlError::ResolveTimeout(clip_slug.to_string(), limit))??,
+        None => fetcher.fetch_source_info(clip_slug, gql).await?
+    };
+    check_gql_errors(&body, clip_slug)?;
 
     let video_source_response: VideoSourceResponse = serde_json::from_str(&body)?;
+    let Some(clip) = video_source_response.data.clip else {
+        return Err(TwdlError::ClipUnavailable { slug: clip_slug.to_string(), reason: None });
+    };
+    if clip.video_qualities.is_empty() {
+        return Err(TwdlError::NoQualities(clip_slug.to_string()));
+    }
 
-    format_source_urls(&video_source_response)
+    let files = format_source_urls(&clip)?;
+    if files.is_empty() {
+        return Err(TwdlError::NoSourceFiles(clip_slug.to_string()));
+    }
+    let info = SourceInfo {
+        duration_ms: clip.duration_seconds.map(|seconds| seconds * 1000),
+        files
+    };
+    if let Some(cache) = &gql.cache {
+        cache.insert(clip_slug, &info);
+    }
+    Ok(info)
 }
 
-pub async fn download_clips(multi: Arc<MultiProgress>, clips: Vec<Clip>, directory: &Path, chunk_size: usize, silent: bool, meta: bool) {
-    let bar = match silent {
-        false => Some(multi.add(ProgressBar::new(clips.len().try_into().unwrap()))),
-        true => None
+// Pause before each end-of-run retry pass, giving transient failures a moment to clear
+const RETRY_PASS_DELAY: Duration = Duration::from_secs(5);
+
+// `on_complete` runs for a clip as soon as its own download finishes, inside the same concurrency slot.
+// So at most `options.concurrency` callbacks run at once, and the next batch of clips waits for them.
+// Nothing is drawn when `multi` is None.
+// Downloaded clips are recorded in `directory`'s manifest after every batch, so an interrupted run keeps its progress.
+// Clips that fail are attempted again in up to `options.retry_passes` passes once all others are done.
+pub async fn download_clips(multi: Option<Arc<MultiProgress>>, client: &reqwest::Client, clips: Vec<Clip>, directory: &Path, gql: &GqlConfig, options: &DownloadOptions, on_complete: Option<&ClipCallback>) -> DownloadReport {
+    let mut report = DownloadReport::default();
+    let mut manifest = Manifest::load(directory).await;
+    // Taken before `sync` drops any clips, so `options.number` gives a clip the same number every run
+    let order: HashMap<String, usize> = clips.iter().enumerate().map(|(i, clip)| (clip.id.to_string(), i)).collect();
+    let number_width = clips.len().to_string().len();
+    let clips = match options.sync {
+        true => {
+            let existing = manifest.ids();
+            let total = clips.len();
+            let clips: Vec<Clip> = clips.into_iter().filter(|clip| !existing.contains(clip.id.as_str())).collect();
+            report.skipped = total - clips.len();
+            info!("Skipping {} clips already in the manifest", report.skipped);
+            clips
+        }
+        false => clips
+    };
+
+    let bar = multi.as_ref().map(|multi| multi.add(ProgressBar::new(clips.len().try_into().unwrap())));
+    let prescan = match options.prescan {
+        true => prescan_clips(client, &clips, gql, options).await,
+        false => Prescan::default()
+    };
+    let byte_options;
+    let options = match (&multi, &options.byte_bar) {
+        (Some(multi), None) => {
+            let byte_bar = match options.prescan {
+                true => ByteBar::presized(byte_progress_bar(multi), prescan.total_size()),
+                false => ByteBar::new(byte_progress_bar(multi))
+            };
+            byte_options = DownloadOptions { byte_bar: Some(byte_bar), ..options.clone() };
+            &byte_options
+        }
+        _ => options
     };
-    for chunk in clips.chunks(chunk_size) {
-        if meta {
-            let futures: Vec<_> = chunk.iter().map(|clip| save_metadata(clip, directory)).collect();
-            let _ = join_all(futures).await;
+    let prescan = &prescan;
+    let multi = multi.as_deref();
+    let started = Instant::now();
+    let mut pending = clips;
+    let mut failures = Vec::new();
+    for pass in 0..=options.retry_passes {
+        if pending.is_empty() {
+            break;
         }
-        let futures: Vec<_> = chunk.iter().map(|clip| download_clip(clip, directory)).collect();
-        let _ = join_all(futures).await;
-        if let Some(ref bar) = bar {
-            bar.inc(chunk.len().try_into().unwrap());
+        // `failures` still holds the previous pass's clips, which become the final failures
+        if pass > 0 && options.retry_budget.as_ref().is_some_and(|budget| budget.is_exhausted()) {
+            warn!("Retry budget used up, not retrying {} failed clips", pending.len());
+            break;
         }
+        if pass > 0 {
+            info!("Retrying {} failed clips (pass {pass}/{})", pending.len(), options.retry_passes);
+            sleep(RETRY_PASS_DELAY).await;
+        }
+
+        failures.clear();
+        for chunk in pending.chunks(options.concurrency) {
+            let futures: Vec<_> = chunk.iter().map(|clip| async move {
+                let _clip_bar = multi.map(|multi| clip_bar(multi, clip));
+                let source = match prescan.entries.get(clip.id.as_str()) {
+                    Some(entry) => entry.source.clone(),
+                    None => match resolve_clip_source(client, clip, gql, options).await {
+                        Ok(source) => source,
+                        Err(err) => return Err((clip, err))
+                    }
+                };
+                // Saved once the source is known so it can be recorded, retries keep the first file
+                if options.metadata && pass == 0 {
+                    save_metadata(clip, Some(&source), directory, options).await;
+                }
+                let stem = match options.number {
+                    true => format!("{:0number_width$}_{}", order[clip.id.as_str()] + 1, clip.id),
+                    false => clip.id.to_string()
+                };
+                let path = match download_clip_from(client, clip, Some(source), directory, &stem, gql, options).await {
+                    Ok(path) => path,
+                    Err(err) => return Err((clip, err))
+                };
+                let (path, gif) = post_process(client, clip, path, directory, options).await;
+                if let Some(callback) = on_complete {
+                    callback(clip, &path).await;
+                }
+                let size = fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or(0);
+                Ok((ManifestEntry::new(clip.id.to_string(), path, size, clip.created_at.to_string()), gif))
+            }).collect();
+            let chunk_started = Instant::now();
+            let results = join_all(futures).await;
+            let chunk_bytes: u64 = results.iter().flatten().map(|(entry, _)| entry.size).sum();
+            report.peak_bytes_per_second = report.peak_bytes_per_second.max(bytes_per_second(chunk_bytes, chunk_started.elapsed().as_secs_f64()));
+            for result in results {
+                match result {
+                    Ok((entry, gif)) => {
+                        report.downloaded.push(DownloadedClip { clip_id: entry.id.clone(), path: entry.path.clone(), size: entry.size, gif });
+                        manifest.record(entry);
+                    }
+                    // Nothing to download yet, retrying within this run wouldn't change that
+                    Err((clip, TwdlError::NoQualities(_))) => {
+                        warn!(clip_id = clip.id.as_str(); "Skipping clip {}, it has no available qualities", clip.id);
+                        report.unavailable.push(clip.id.to_string());
+                    }
+                    Err((clip, err @ TwdlError::ClipUnavailable { .. })) => {
+                        warn!(clip_id = clip.id.as_str(); "Skipping clip: {err}");
+                        report.unavailable.push(clip.id.to_string());
+                    }
+                    Err((clip, err @ TwdlError::BelowMinQuality { .. })) => {
+                        warn!(clip_id = clip.id.as_str(); "Skipping clip: {err}");
+                        report.low_quality.push(SkippedClip { clip_id: clip.id.to_string(), reason: err.to_string() });
+                    }
+                    Err((clip, err)) => {
+                        error!(clip_id = clip.id.as_str(); "Failed to download clip: {} ({err})", clip.id);
+                        failures.push((clip.clone(), err.to_string()));
+                    }
+                }
+            }
+            if let Err(err) = manifest.save(directory).await {
+                error!("{err}");
+            }
+            // Retried clips were already counted on the first pass
+            if let Some(bar) = bar.as_ref().filter(|_| pass == 0) {
+                bar.inc(chunk.len().try_into().unwrap());
+            }
+        }
+        pending = failures.iter().map(|(clip, _)| clip.clone()).collect();
+    }
+
+    report.downloaded.sort_by_key(|clip| order.get(&clip.clip_id).copied());
+    report.download_seconds = started.elapsed().as_secs_f64();
+    report.average_bytes_per_second = bytes_per_second(report.total_bytes(), report.download_seconds);
+    report.failed = failures.into_iter()
+        .map(|(clip, error)| FailedClip { clip_id: clip.id.to_string(), url: clip.url, error })
+        .collect();
+    if !report.failed.is_empty() {
+        let ids: Vec<&str> = report.failed.iter().map(|failed| failed.clip_id.as_str()).collect();
+        error!("{} clips could not be downloaded: {}", ids.len(), ids.join(", "));
     }
+    report
 }
 
-pub async fn download_file(url: Url, file: &PathBuf) {
-    let client = reqwest::Client::new();
-    let response = match client.get(url).send().await {
-        Ok(resp) => resp,
-        Err(e) => {
-            error!("Failed to send request: {e}");
-            return;
+// Downloads clips known only by their slug, through GQL alone so no twitch credentials are needed.
+// Without a helix `Clip` there's nothing for metadata, post-processing, the manifest or retry passes to work from,
+// so only the clip files themselves are downloaded. Files are named after the slug.
+pub async fn download_slugs(multi: Option<&MultiProgress>, client: &reqwest::Client, slugs: &[String], directory: &Path, gql: &GqlConfig, options: &DownloadOptions) -> DownloadReport {
+    let bar = multi.map(|multi| multi.add(ProgressBar::new(slugs.len() as u64)));
+    let started = Instant::now();
+    let mut results: Vec<_> = stream::iter(slugs.iter().enumerate())
+        .map(|(i, slug)| {
+            let bar = bar.as_ref();
+            async move {
+                let result = download_slug(client, slug, directory, gql, options).await;
+                if let Some(bar) = bar {
+                    bar.inc(1);
+                }
+                (i, slug, result)
+            }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    results.sort_by_key(|(i, _, _)| *i);
+
+    let mut report = DownloadReport::default();
+    for (_, slug, result) in results {
+        match result {
+            Ok((path, size)) => report.downloaded.push(DownloadedClip { clip_id: slug.clone(), path, size, gif: None }),
+            Err(TwdlError::NoQualities(_) | TwdlError::ClipUnavailable { .. }) => {
+                warn!(clip_id = slug.as_str(); "Skipping clip {slug}, it's unavailable");
+                report.unavailable.push(slug.clone());
+            }
+            Err(err @ TwdlError::BelowMinQuality { .. }) => {
+                warn!(clip_id = slug.as_str(); "Skipping clip: {err}");
+                report.low_quality.push(SkippedClip { clip_id: slug.clone(), reason: err.to_string() });
+            }
+            Err(err) => {
+                error!(clip_id = slug.as_str(); "Failed to download clip: {slug} ({err})");
+                report.failed.push(FailedClip { clip_id: slug.clone(), url: format!("https://clips.twitch.tv/{slug}"), error: err.to_string() });
+            }
         }
+    }
+    report.download_seconds = started.elapsed().as_secs_f64();
+    report.average_bytes_per_second = bytes_per_second(report.total_bytes(), report.download_seconds);
+    report
+}
+
+async fn download_slug(client: &reqwest::Client, slug: &str, directory: &Path, gql: &GqlConfig, options: &DownloadOptions) -> Result<(PathBuf, u64)> {
+    let files = get_video_source_files(client, slug, gql).await?;
+    check_min_quality(&files, options.min_quality, slug)?;
+    let source = select_source_file(&files, options.quality)
+        .ok_or_else(|| TwdlError::NoSourceFiles(slug.to_string()))?;
+    let path = clip_path(directory, slug);
+    download_source(client, slug, source, &path, gql, options).await?;
+    let size = fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or(0);
+    Ok((path, size))
+}
+
+// reqwest's content_length() is always 0 for HEAD responses, so the header is read directly
+pub(crate) async fn head_content_length(client: &reqwest::Client, url: Url) -> Result<Option<u64>> {
+    let response = client.head(url).send().await?
+        .error_for_status()?;
+    Ok(response.headers().get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok()))
+}
+
+// Extra files and processing for a downloaded clip, done before `on_complete` sees it.
+// Returns the clip's final file, which is the audio when the video was deleted, and its GIF preview if one was made.
+async fn post_process(client: &reqwest::Client, clip: &Clip, path: PathBuf, directory: &Path, options: &DownloadOptions) -> (PathBuf, Option<PathBuf>) {
+    if options.thumbnails {
+        save_thumbnail(client, clip, directory).await;
+    }
+    if options.nfo {
+        nfo::save_nfo(clip, directory).await;
+    }
+    let embedded = match options.embed_metadata {
+        true => ffmpeg::embed_metadata(clip, &path).await,
+        false => Ok(())
     };
+    if let Err(err) = embedded {
+        warn!(clip_id = clip.id.as_str(); "Failed to embed metadata in clip {} ({err})", clip.id);
+    }
+    // Made before extracting audio, which may delete the video
+    let gif = match &options.gif {
+        Some(gif_options) => save_gif(clip.id.as_str(), &path, gif_options).await,
+        None => None
+    };
+    let video = path.clone();
+    let path = match &options.audio_format {
+        Some(format) => extract_audio(clip.id.as_str(), path, format, options.delete_video).await,
+        None => path
+    };
+    if options.checksums {
+        // The sidecar written during the download no longer matches once ffmpeg has rewritten the file
+        if options.embed_metadata && fs::try_exists(&video).await.unwrap_or(false) {
+            save_checksum(clip.id.as_str(), &video, None).await;
+        }
+        if path != video {
+            save_checksum(clip.id.as_str(), &path, None).await;
+        }
+        if !fs::try_exists(&video).await.unwrap_or(true) {
+            let _ = fs::remove_file(sidecar_path(&video)).await;
+        }
+    }
+    (path, gif)
+}
 
-    let mut stream = response.bytes_stream();
+async fn save_gif(clip_id: &str, path: &Path, options: &ffmpeg::GifOptions) -> Option<PathBuf> {
+    let gif = path.with_extension("gif");
+    match ffmpeg::gif(path, &gif, options).await {
+        Ok(()) => Some(gif),
+        Err(err) => {
+            warn!(clip_id = clip_id; "Failed to make a GIF of clip {clip_id} ({err})");
+            None
+        }
+    }
+}
 
-    let mut output = match File::create(&file).await {
-        Ok(f) => f,
-        Err(e) => {
-            error!("Failed to create file {}: {}", file.display(), e);
-            return;
+// Keeps the video when extraction fails, so nothing is lost
+pub async fn extract_audio(clip_id: &str, path: PathBuf, format: &str, delete_video: bool) -> PathBuf {
+    let audio = match ffmpeg::extract_audio(&path, format).await {
+        Ok(audio) => audio,
+        Err(err) => {
+            warn!(clip_id = clip_id; "Failed to extract audio from clip {clip_id} ({err})");
+            return path;
         }
     };
+    if !delete_video {
+        return path;
+    }
+    match fs::remove_file(&path).await {
+        Ok(()) => audio,
+        Err(err) => {
+            warn!(clip_id = clip_id; "Failed to delete {} ({err})", path.display());
+            path
+        }
+    }
+}
 
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(bytes) => {
-                if let Err(e) = output.write_all(&bytes).await {
-                    error!("Failed to write to file {}: {}", file.display(), e);
-                    return;
-                }
+async fn emit(options: &DownloadOptions, event: DownloadEvent) {
+    if let Some(events) = &options.events {
+        // A dropped receiver just means nobody is listening anymore
+        let _ = events.send(event).await;
+    }
+}
+
+// Downloads `url` to `file`, retrying failed or timed out attempts up to `options.retries` times.
+// `clip_id` tags the progress events sent for this file.
+pub async fn download_file(client: &reqwest::Client, clip_id: &str, url: Url, file: &PathBuf, options: &DownloadOptions) -> Result<()> {
+    let client = options.client_pool.as_ref().and_then(|pool| pool.next()).unwrap_or(client);
+    let mut attempt = 0;
+    loop {
+        match try_download_file(client, clip_id, url.clone(), file, options).await {
+            Ok(()) => {
+                debug!(clip_id = clip_id; "Downloaded file to {}", file.display());
+                emit(options, DownloadEvent::Completed { clip_id: clip_id.to_string(), path: file.clone() }).await;
+                return Ok(());
             }
-            Err(e) => {
-                error!("Error while downloading: {e}");
-                return;
+            // Only takes from the budget when a retry would otherwise happen. A refused URL would just be refused again,
+            // `download_source` resolves a new one instead.
+            Err(err) if attempt < options.retries && !err.is_forbidden() && options.retry_budget.as_ref().is_none_or(|budget| budget.try_take()) => {
+                attempt += 1;
+                warn!(clip_id = clip_id; "Download of {} failed ({err}), retrying ({attempt}/{})", file.display(), options.retries);
+                sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+            Err(err) => {
+                emit(options, DownloadEvent::Failed { clip_id: clip_id.to_string(), error: err.to_string() }).await;
+                return Err(err);
             }
         }
     }
+}
 
-    debug!("Downloaded file to {}", file.display());
+// Downloads `source` with `download_file`. Sources resolved early in a long run, or by the prescan, can outlive
+// their playback token and get refused, so the clip is resolved again and the same quality fetched from the new URL.
+pub async fn download_source(client: &reqwest::Client, clip_id: &str, source: &SourceFile, file: &PathBuf, gql: &GqlConfig, options: &DownloadOptions) -> Result<()> {
+    match download_file(client, clip_id, source.url.clone(), file, options).await {
+        Err(err) if err.is_forbidden() => {
+            warn!(clip_id = clip_id; "Source URL for clip {clip_id} was refused, resolving it again");
+            if let Some(cache) = &gql.cache {
+                cache.remove(clip_id);
+            }
+            let files = get_video_source_files(client, clip_id, gql).await?;
+            let fresh = files.iter()
+                .find(|file| file.quality == source.quality && file.frame_rate == source.frame_rate)
+                .or_else(|| select_source_file(&files, options.quality))
+                .ok_or_else(|| TwdlError::NoSourceFiles(clip_id.to_string()))?;
+            download_file(client, clip_id, fresh.url.clone(), file, options).await
+        }
+        result => result
+    }
 }
 
-pub async fn save_metadata(clip: &Clip, directory: &Path) {
-    let clip_json = match serde_json::to_string(&clip) {
-        Ok(json) => json,
-        Err(_) => {
-            error!("Filed to serialize metadata for clip {}", &clip.id);
+// Writes to `{file}.part` and renames it once complete, so `file` only ever exists in full.
+// A crash midway leaves just the `.part` file behind.
+async fn try_download_file(client: &reqwest::Client, clip_id: &str, url: Url, file: &PathBuf, options: &DownloadOptions) -> Result<()> {
+    let mut part = file.clone().into_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    let split_length = match options.split > 1 {
+        true => range_length(client, url.clone()).await,
+        false => None
+    };
+
+    // Declared first so it drops after `output`, windows can't remove a file that's still open
+    let guard = PartialFile(Some(&part));
+    let digest = match split_length {
+        // Ranges arrive out of order, so the finished file is hashed instead
+        Some(length) => {
+            download_split(client, clip_id, url, &part, length, options).await?;
+            match options.checksums {
+                true => Some(hash_file(&part).await?),
+                false => None
+            }
+        }
+        None => {
+            let output = File::create(&part).await
+                .map_err(|err| TwdlError::io(format!("Failed to create file {}", part.display()), err))?;
+            match options.checksums {
+                true => {
+                    let mut output = HashingWriter::new(output);
+                    download_to(client, clip_id, url, &mut output, options).await?;
+                    Some(output.finish())
+                }
+                false => {
+                    let mut output = output;
+                    download_to(client, clip_id, url, &mut output, options).await?;
+                    None
+                }
+            }
+        }
+    };
+
+    fs::rename(&part, file).await
+        .map_err(|err| TwdlError::io(format!("Failed to rename {} to {}", part.display(), file.display()), err))?;
+    guard.keep();
+    if let Some(digest) = digest {
+        save_checksum(clip_id, file, Some(digest)).await;
+    }
+    Ok(())
+}
+
+// Hashes `file` first when `digest` is None. The download itself is kept either way.
+async fn save_checksum(clip_id: &str, file: &Path, digest: Option<String>) {
+    let digest = match digest {
+        Some(digest) => Ok(digest),
+        None => hash_file(file).await
+    };
+    let written = match digest {
+        Ok(digest) => write_sidecar(file, &digest).await,
+        Err(err) => Err(err)
+    };
+    if let Err(err) = written {
+        warn!(clip_id = clip_id; "Failed to save the checksum of {} ({err})", file.display());
+    }
+}
+
+// Deletes a file that's still being written when dropped, so neither an error nor a cancelled
+// download (e.g. a timed out future) leaves an error page or half a clip behind
+struct PartialFile<'a>(Option<&'a Path>);
+
+impl PartialFile<'_> {
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for PartialFile<'_> {
+    fn drop(&mut self) {
+        if let Some(path) = self.0 {
+            // Drop can't await, the file is small enough to remove synchronously
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Streams `url` into `sink` in a single attempt, returning the number of bytes written.
+// Nothing is retried here since the sink may already hold part of the body, see `download_file`.
+pub async fn download_to<W: AsyncWrite + Unpin>(client: &reqwest::Client, clip_id: &str, url: Url, sink: &mut W, options: &DownloadOptions) -> Result<u64> {
+    match options.timeout {
+        Some(limit) => timeout(limit, stream_to(client, clip_id, url, sink, options)).await
+            .unwrap_or(Err(TwdlError::Timeout(limit))),
+        None => stream_to(client, clip_id, url, sink, options).await
+    }
+}
+
+async fn stream_to<W: AsyncWrite + Unpin>(client: &reqwest::Client, clip_id: &str, url: Url, sink: &mut W, options: &DownloadOptions) -> Result<u64> {
+    let response = client.get(url).send().await?
+        .error_for_status()?;
+    // Expired source URLs answer with an error page instead of failing outright
+    let content_type = response.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    if let Some(content_type) = content_type.filter(|ct| ct.starts_with("text/") || ct.contains("json") || ct.contains("xml")) {
+        return Err(TwdlError::NotVideo(clip_id.to_string(), content_type.to_string()));
+    }
+    let expected = response.content_length();
+    emit(options, DownloadEvent::Started { clip_id: clip_id.to_string(), total_bytes: expected }).await;
+    let mut progress = ByteProgress::new(options.byte_bar.as_ref());
+    progress.start(expected);
+
+    let mut stream = response.bytes_stream();
+
+    // The first bytes are held back until they can be checked for an mp4 header
+    let mut header = Vec::with_capacity(MP4_HEADER_LEN);
+    let mut written = 0;
+    while let Some(chunk) = stream.next().await {
+        let bytes = chunk?;
+        if let Some(limiter) = &options.rate_limiter {
+            limiter.acquire(bytes.len()).await;
+        }
+        let pending = match header.len() < MP4_HEADER_LEN {
+            true => {
+                header.extend_from_slice(&bytes);
+                if header.len() < MP4_HEADER_LEN {
+                    continue;
+                }
+                if !looks_like_mp4(&header) {
+                    return Err(TwdlError::NotVideo(clip_id.to_string(), "missing mp4 header".to_string()));
+                }
+                &header[..]
+            }
+            false => &bytes[..]
+        };
+        sink.write_all(pending).await
+            .map_err(|err| TwdlError::io(format!("Failed to write clip {clip_id}"), err))?;
+        written += pending.len() as u64;
+        progress.advance(pending.len() as u64);
+        emit(options, DownloadEvent::Progress { clip_id: clip_id.to_string(), bytes: written }).await;
+    }
+    if header.len() < MP4_HEADER_LEN {
+        return Err(TwdlError::NotVideo(clip_id.to_string(), format!("only {} bytes long", header.len())));
+    }
+    // A dropped connection can end the stream early without an error, the partial file is removed and retried
+    if let Some(expected) = expected.filter(|expected| *expected != written) {
+        return Err(TwdlError::Incomplete(clip_id.to_string(), expected, written));
+    }
+    sink.flush().await
+        .map_err(|err| TwdlError::io(format!("Failed to write clip {clip_id}"), err))?;
+    progress.finish();
+
+    Ok(written)
+}
+
+// Thumbnails are small, so they're fetched in one go and kept out of the progress events and byte bar.
+// Clips still processing on twitch's side have an empty or placeholder thumbnail, those are skipped.
+pub async fn save_thumbnail(client: &reqwest::Client, clip: &Clip, directory: &Path) {
+    if clip.thumbnail_url.is_empty() || clip.thumbnail_url.contains("404_") {
+        debug!(clip_id = clip.id.as_str(); "Clip {} has no thumbnail", clip.id);
+        return;
+    }
+    let response = match client.get(&clip.thumbnail_url).send().await.and_then(|response| response.error_for_status()) {
+        Ok(response) => response,
+        Err(err) => {
+            warn!(clip_id = clip.id.as_str(); "Failed to fetch thumbnail for clip {} ({err})", clip.id);
+            return;
+        }
+    };
+    let bytes = match response.bytes().await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            warn!(clip_id = clip.id.as_str(); "Failed to fetch thumbnail for clip {} ({err})", clip.id);
             return;
         }
     };
+    let output_path = directory.join(format!("{}.jpg", &clip.id));
+    if let Err(err) = fs::write(&output_path, bytes).await {
+        error!(clip_id = clip.id.as_str(); "Failed to write thumbnail file: {err}");
+    }
+}
+
+// Written as `{ clip, download }`, where download is the source file that was picked, or null when unknown
+pub async fn save_metadata(clip: &Clip, source: Option<&SourceFile>, directory: &Path, options: &DownloadOptions) {
     let output_path = directory
         .join(PathBuf::from_str(&format!("{}_metadata.json", &clip.id)).unwrap());
+    // Re-syncs would otherwise rewrite every file, and undo any edits made to them
+    if !options.overwrite_metadata && fs::try_exists(&output_path).await.unwrap_or(false) {
+        debug!(clip_id = clip.id.as_str(); "Keeping existing metadata for clip {}", clip.id);
+        return;
+    }
+    let download = source.map(|source| serde_json::json!({
+        "quality": source.quality,
+        "frame_rate": source.frame_rate,
+        "url": source.url.as_str()
+    }));
+    let metadata = serde_json::json!({ "clip": clip, "download": download });
+    let serialized = match options.pretty_metadata {
+        true => serde_json::to_string_pretty(&metadata),
+        false => serde_json::to_string(&metadata)
+    };
+    let clip_json = match serialized {
+        Ok(json) => json,
+        Err(_) => {
+            error!(clip_id = clip.id.as_str(); "Filed to serialize metadata for clip {}", &clip.id);
+            return;
+        }
+    };
     match fs::write(output_path, clip_json).await {
         Ok(_) => {}
         Err(err) => {
-            error!("Failed to write metadata file: {err}");
+            error!(clip_id = clip.id.as_str(); "Failed to write metadata file: {err}");
         }
     }
 
 }
 
-pub async fn download_clip(clip: &Clip, directory: &Path) {
-    let source_files = match get_video_source_files(&clip.id).await {
+// Returns where the clip was written
+pub async fn download_clip(client: &reqwest::Client, clip: &Clip, directory: &Path, gql: &GqlConfig, options: &DownloadOptions) -> Result<PathBuf> {
+    download_clip_from(client, clip, None, directory, clip.id.as_str(), gql, options).await
+}
+
+// Skips resolving the clip when its `source` is already known, the file is named after `stem`
+async fn download_clip_from(client: &reqwest::Client, clip: &Clip, source: Option<SourceFile>, directory: &Path, stem: &str, gql: &GqlConfig, options: &DownloadOptions) -> Result<PathBuf> {
+    let source = match source {
+        Some(source) => source,
+        None => resolve_clip_source(client, clip, gql, options).await?
+    };
+    let path = clip_path(directory, stem);
+    download_source(client, clip.id.as_str(), &source, &path, gql, options).await?;
+    Ok(path)
+}
+
+async fn resolve_clip_source(client: &reqwest::Client, clip: &Clip, gql: &GqlConfig, options: &DownloadOptions) -> Result<SourceFile> {
+    let source_files = match get_video_source_files(client, clip.id.as_str(), gql).await
+        .and_then(|files| check_min_quality(&files, options.min_quality, clip.id.as_str()).map(|()| files)) {
         Ok(files) => files,
         Err(err) => {
-            error!("Failed to download clip: {} ({err})", clip.id);
-            return;
+            emit(options, DownloadEvent::Failed { clip_id: clip.id.to_string(), error: err.to_string() }).await;
+            return Err(err);
         }
     };
-    let best = match source_files.iter().max() {
-        Some(best) => best,
-        None => {
-            error!("Could not find source file for clip: {}", clip.id);
-            return;
-        }
-    };
-    let url = best.url.clone();
-    let path = match PathBuf::from_str(&format!("{}.mp4", &clip.id)) {
-        Ok(path) => path,
-        Err(err) => {
-            error!("Failed to specify path: {err}");
-            return;
+    let best = select_source_file(&source_files, options.quality)
+        .ok_or_else(|| TwdlError::NoSourceFiles(clip.id.to_string()))?;
+    Ok(best.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers every lookup with the same canned body
+    struct MockFetcher(String);
+
+    impl SourceFetcher for MockFetcher {
+        fn fetch_source_info<'a>(&'a self, _clip_slug: &'a str, _gql: &'a GqlConfig) -> BoxFuture<'a, Result<String>> {
+            Box::pin(async move { Ok(self.0.clone()) })
         }
-    };
-    let path = directory.join(path);
-    download_file(url.clone(), &path).await;
-}
\ No newline at end of file
+    }
+
+    fn source_body(qualities: &str) -> String {
+        format!(r#"{{
+            "data": {{
+                "clip": {{
+                    "playbackAccessToken": {{ "signature": "abc123", "value": "{{\"clip_uri\":\"x\"}}" }},
+                    "durationSeconds": 30,
+                    "videoQualities": [{qualities}]
+                }}
+            }},
+            "extensions": {{ "durationMilliseconds": 12, "operationName": "VideoAccessToken_Clip", "requestID": "1" }}
+        }}"#)
+    }
+
+    #[tokio::test]
+    async fn signs_every_source_url() {
+        let fetcher = MockFetcher(source_body(r#"
+            { "quality": "1080", "frameRate": 60.0, "sourceURL": "https://clips.example/1080.mp4" },
+            { "quality": "720", "frameRate": 30.0, "sourceURL": "https://clips.example/720.mp4" }
+        "#));
+        let info = get_video_source_info(&fetcher, "SomeClip", &GqlConfig::default()).await.unwrap();
+        assert_eq!(info.duration_ms, Some(30_000));
+        assert_eq!(info.files.len(), 2);
+        assert_eq!(info.files[0].quality, 1080);
+        assert_eq!(info.files[0].frame_rate, 60);
+        assert_eq!(info.files[0].url.as_str(), "https://clips.example/1080.mp4?sig=abc123&token=%7B%22clip%5Furi%22%3A%22x%22%7D");
+        assert_eq!(info.files[1].quality, 720);
+    }
+
+    #[tokio::test]
+    async fn null_clip_is_unavailable() {
+        let fetcher = MockFetcher(r#"{"data":{"clip":null}}"#.to_string());
+        let err = get_video_source_files(&fetcher, "GoneClip", &GqlConfig::default()).await.unwrap_err();
+        assert!(matches!(err, TwdlError::ClipUnavailable { ref slug, reason: None } if slug == "GoneClip"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn errors_without_data_are_gql_errors() {
+        let fetcher = MockFetcher(r#"{"errors":[{"message":"service timeout"},{"message":"try again"}]}"#.to_string());
+        let err = get_video_source_files(&fetcher, "SomeClip", &GqlConfig::default()).await.unwrap_err();
+        assert!(matches!(err, TwdlError::Gql(ref message) if message == "service timeout, try again"), "{err}");
+    }
+
+    #[test]
+    fn null_clip_body_is_reported_before_parsing() {
+        let err = check_gql_errors(r#"{"data":{"clip":null}}"#, "GoneClip").unwrap_err();
+        assert!(matches!(err, TwdlError::ClipUnavailable { reason: None, .. }), "{err}");
+    }
+
+    #[test]
+    fn errors_body_is_reported_before_parsing() {
+        let err = check_gql_errors(r#"{"errors":[{"message":"invalid slug"}],"data":null}"#, "bad").unwrap_err();
+        assert!(matches!(err, TwdlError::Gql(ref message) if message == "invalid slug"), "{err}");
+    }
+
+    #[test]
+    fn full_response_passes_the_error_check() {
+        assert!(check_gql_errors(&source_body(""), "SomeClip").is_ok());
+    }
+
+    #[tokio::test]
+    async fn null_clip_keeps_the_errors_as_its_reason() {
+        let fetcher = MockFetcher(r#"{"errors":[{"message":"clip is subscriber only"}],"data":{"clip":null}}"#.to_string());
+        let err = get_video_source_files(&fetcher, "SubClip", &GqlConfig::default()).await.unwrap_err();
+        assert!(matches!(err, TwdlError::ClipUnavailable { reason: Some(ref reason), .. } if reason == "clip is subscriber only"), "{err}");
+    }
+
+    #[test]
+    fn parses_each_quality_label() {
+        assert_eq!(parse_quality("720"), Some(720));
+        assert_eq!(parse_quality("1080p"), Some(1080));
+        assert_eq!(parse_quality("720p60"), Some(720));
+        assert_eq!(parse_quality("audio_only"), Some(0));
+        assert_eq!(parse_quality("7x20"), None);
+    }
+
+    #[tokio::test]
+    async fn skips_unparseable_qualities() {
+        let fetcher = MockFetcher(source_body(r#"
+            { "quality": "1080p60", "frameRate": 60.0, "sourceURL": "https://clips.example/1080.mp4" },
+            { "quality": "4k?", "frameRate": 30.0, "sourceURL": "https://clips.example/4k.mp4" }
+        "#));
+        let files = get_video_source_files(&fetcher, "SomeClip", &GqlConfig::default()).await.unwrap();
+        assert_eq!(files.iter().map(|file| file.quality).collect::<Vec<_>>(), [1080]);
+    }
+}
@@ -1,17 +1,43 @@
 use chrono::{DateTime, TimeDelta, Utc};
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use dateparser::parse;
-use futures_util::future::join_all;
-use indicatif::MultiProgress;
+use dialoguer::{Confirm, MultiSelect};
+use futures_util::{stream, StreamExt};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use serde::{Deserialize, Serialize};
-use twdl::{download_clips, get_video_source_files};
-use twitch_api::{twitch_oauth2::AppAccessToken, types::UserId};
-use std::{path::PathBuf, process, str::FromStr, sync::Arc};
-use regex::Regex;
-use tokio::fs::read;
-use log::{error, info};
+use twdl::{download_clips, get_video_source_files, get_video_source_info, playlist::{write_m3u, PlaylistEntry}, build_http_client, clip_callback, ClipCallback, DownloadOptions, GqlConfig, HttpOptions, SourceFile, SourceInfo};
+use twdl::batch::{load_batch, BatchChannel};
+use twdl::client_pool::ClientPool;
+use twdl::events::write_json_lines;
+use twdl::exec::ExecHook;
+use twdl::ffmpeg::{concat, ffmpeg_available, trim, GifOptions};
+use twitch_api::{helix::clips::Clip, types::{CategoryId, UserId}};
+use twdl::twitch_utils::{ClipSource, TwitchSession};
+use std::{collections::HashSet, env, fs::OpenOptions, io::{self, IsTerminal, Write}, path::{Path, PathBuf}, process, str::FromStr, sync::{Arc, Mutex}, time::Duration};
+use tokio::{fs::read, sync::mpsc::channel};
+use log::{error, info, warn};
+use regex::{Captures, Regex, RegexBuilder};
 
-use twdl::cli::{Cli, ClipCommandArgs, ChannelCommandArgs, Commands};
+use twdl::config::{load_config, Config};
+use twdl::error::TwdlError;
+use twdl::manifest::Manifest;
+use twdl::rate_limit::RateLimiter;
+use twdl::retry_budget::RetryBudget;
+use twdl::source_cache::{SourceCache, DEFAULT_SOURCE_TTL};
+use twdl::user_cache;
+use twdl::report::{write_failures, DownloadReport, FailedClip, RunSummary};
+use twdl::webhook::{notify, notify_discord};
+use twdl::verify::{find_clips, read_manifest, verify_clips, VerifyStatus};
+use twdl::cli::{AuthMode, Cli, ClipCommandArgs, ChannelCommandArgs, ChannelsCommandArgs, ColorMode, Commands, GameCommandArgs, InfoCommandArgs, SearchCommandArgs, LinkFormat, LogFormat, LoginCommandArgs, UserCommandArgs, VerifyCommandArgs};
+
+// Credentials stored by `twdl login` live under this service/user pair in the OS keyring
+const KEYRING_SERVICE: &str = "twdl";
+const KEYRING_USER: &str = "twitch-credentials";
+
+// Exit codes, listed in the README. clap exits with 2 for invalid arguments.
+const EXIT_ERROR: i32 = 1;
+const EXIT_CLIP_UNAVAILABLE: i32 = 3;
+const EXIT_INVALID_CLIP: i32 = 4;
 
 #[derive(Deserialize, Serialize, Debug)]
 struct TwitchCredentials {
@@ -21,211 +47,1644 @@ struct TwitchCredentials {
 
 fn exit_with_error_msg(msg: &str, code: Option<i32>) -> ! {
     error!("{msg}");
-    process::exit(code.unwrap_or(1));
+    process::exit(code.unwrap_or(EXIT_ERROR));
 }
 
-async fn login_or_id(id: &Option<u32>, login: &Option<String>, token: &AppAccessToken) -> UserId {
+async fn login_or_id(id: &Option<u32>, login: &Option<String>, session: &TwitchSession) -> UserId {
     match (id, login) {
         (None, None) => {
             error!("Either broadcaster login or id is required");
-            process::exit(1);
+            process::exit(EXIT_ERROR);
         }
         (Some(id), _) => id.to_string().into(),
         (None, Some(login)) => {
-            match twdl::twitch_utils::get_broadcaster_id(login, token).await {
+            match twdl::twitch_utils::get_broadcaster_id(login, session).await {
                 Ok(Some(id)) => id,
-                _ => exit_with_error_msg("Error finding user with that login", Some(1))
+                _ => exit_with_error_msg("Error finding user with that login", Some(EXIT_ERROR))
             }
         }
     }
 }
 
+async fn game_name_or_id(id: &Option<u32>, name: &Option<String>, session: &TwitchSession) -> CategoryId {
+    match (id, name) {
+        (None, None) => exit_with_error_msg("Either game name or id is required", Some(EXIT_ERROR)),
+        (Some(id), _) => id.to_string().into(),
+        (None, Some(name)) => {
+            match twdl::twitch_utils::get_game_id(name, session).await {
+                Ok(Some(id)) => id,
+                _ => exit_with_error_msg("Error finding game with that name", Some(EXIT_ERROR))
+            }
+        }
+    }
+}
 
 fn interpret_date(date: String) -> DateTime<Utc> {
+    if let Some(date) = parse_epoch(&date) {
+        return date;
+    }
     match parse(&date) {
         Ok(date) => {
             // Convert into owned `Timestamp`
             date
         }
-        Err(err) => exit_with_error_msg(&format!("Failed to interpret datetime: {err}"), Some(1)),
+        Err(err) => exit_with_error_msg(&format!("Failed to interpret datetime: {err}"), Some(EXIT_ERROR)),
+    }
+}
+
+// Unix seconds when 10 digits long, or milliseconds when 13.
+// Other digit strings like 20240101 are left for dateparser instead of becoming a date in 1970
+fn parse_epoch(date: &str) -> Option<DateTime<Utc>> {
+    if !date.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let timestamp: i64 = date.parse().ok()?;
+    match date.len() {
+        10 => DateTime::from_timestamp(timestamp, 0),
+        13 => DateTime::from_timestamp_millis(timestamp),
+        _ => None
+    }
+}
+
+// Equal bounds count as reversed, they can't contain any clip
+fn is_ordered(start: &str, end: &str) -> bool {
+    interpret_date(end.to_string()) > interpret_date(start.to_string())
+}
+
+// Checked before any requests are made, a reversed range would otherwise just find no clips
+fn validate_date_range(start: Option<&String>, end: Option<&String>) {
+    let (Some(start), Some(end)) = (start, end) else {
+        return;
+    };
+    if !is_ordered(start, end) {
+        exit_with_error_msg(&format!("The end time ({end}) must be after the start time ({start})"), Some(EXIT_ERROR));
+    }
+}
+
+fn validate_created_range(after: Option<&String>, before: Option<&String>) {
+    let (Some(after), Some(before)) = (after, before) else {
+        return;
+    };
+    if !is_ordered(after, before) {
+        exit_with_error_msg(&format!("--created-before ({before}) must be after --created-after ({after})"), Some(EXIT_ERROR));
+    }
+}
+
+// --created-after/--created-before, applied to clips after they're fetched
+fn filter_created(clips: Vec<Clip>, after: &Option<String>, before: &Option<String>) -> Vec<Clip> {
+    if after.is_none() && before.is_none() {
+        return clips;
+    }
+    let after = after.clone().map(interpret_date);
+    let before = before.clone().map(interpret_date);
+    let total = clips.len();
+    let clips: Vec<Clip> = clips.into_iter()
+        .filter(|clip| {
+            let created_at = twdl::twitch_utils::convert_ts(&clip.created_at);
+            after.is_none_or(|after| created_at > after) && before.is_none_or(|before| created_at < before)
+        })
+        .collect();
+    info!("Kept {} of {total} clips created in the given range", clips.len());
+    clips
+}
+
+// Use user defined dates, falling back to `default_start` and now
+fn interpret_datetimes(start: Option<String>, end: Option<String>, default_start: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    if start.is_none() && end.is_some() {
+        exit_with_error_msg("An end time requires a start time", Some(EXIT_ERROR));
     }
+    let start = match start {
+        Some(str) => interpret_date(str),
+        None => default_start
+    };
+    let end = match end {
+        Some(str) => interpret_date(str),
+        None => Utc::now()
+    };
+    (start, end)
 }
 
+// --since, the `since` leading up to `now`
+fn since_window(since: TimeDelta, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    (now - since, now)
+}
 
-async fn load_credentials(creds: String) -> TwitchCredentials {
+// Just after the newest clip already in `output_path`'s manifest, for --since-last-run
+async fn last_run_start(since_last_run: bool, output_path: &Path) -> Option<DateTime<Utc>> {
+    if !since_last_run {
+        return None;
+    }
+    let latest = Manifest::load(output_path).await.latest_created_at();
+    match latest {
+        Some(latest) => info!("Fetching clips created after {latest}"),
+        None => info!("No previous run found in {}, using the given date range", output_path.display())
+    }
+    latest.map(|latest| latest + TimeDelta::seconds(1))
+}
+
+fn credentials_from_env() -> Option<TwitchCredentials> {
+    match (env::var("TWITCH_CLIENT_ID"), env::var("TWITCH_CLIENT_SECRET")) {
+        (Ok(client_id), Ok(client_secret)) => Some(TwitchCredentials { client_id, client_secret }),
+        _ => None
+    }
+}
+
+fn keyring_entry() -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+}
+
+fn credentials_from_keyring() -> Option<TwitchCredentials> {
+    let stored = keyring_entry().ok()?.get_password().ok()?;
+    serde_json::from_str(&stored).ok()
+}
+
+// Read credentials from the given file, falling back to the environment and then the OS keyring
+async fn load_credentials(creds: Option<String>) -> TwitchCredentials {
+    let creds = match creds {
+        Some(creds) => creds,
+        None => match credentials_from_env().or_else(credentials_from_keyring) {
+            Some(creds) => return creds,
+            None => exit_with_error_msg("Credentials are required, pass --credentials, set TWITCH_CLIENT_ID and TWITCH_CLIENT_SECRET or run `twdl login`", Some(EXIT_ERROR))
+        }
+    };
     let path = match PathBuf::from_str(&creds) {
         Ok(path) => path,
-        Err(_) => exit_with_error_msg(&format!("Invalid credentials path: {}", creds), Some(1))
+        Err(_) => exit_with_error_msg(&format!("Invalid credentials path: {}", creds), Some(EXIT_ERROR))
     };
-    let contents = match read(path).await {
+    let contents = match read(&path).await {
         Ok(contents) => {
             match String::from_utf8(contents) {
                 Ok(str) => str,
-                Err(_) => exit_with_error_msg("Failed to interpret creds file as text", Some(1))
+                Err(_) => exit_with_error_msg("Failed to interpret creds file as text", Some(EXIT_ERROR))
             }
         }
-        Err(_) => exit_with_error_msg("Failed to read from credentials file", Some(1))
+        Err(_) => exit_with_error_msg("Failed to read from credentials file", Some(EXIT_ERROR))
     };
-    let creds: TwitchCredentials = match serde_json::from_str(&contents) {
-        Ok(creds) => creds,
-        Err(err) => exit_with_error_msg(&format!("json file has invalid formatting: {err}"), Some(1))
+    // Anything that isn't explicitly toml is treated as json, as before
+    let is_toml = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+    let creds: TwitchCredentials = if is_toml {
+        match toml::from_str(&contents) {
+            Ok(creds) => creds,
+            Err(err) => exit_with_error_msg(&format!("toml file has invalid formatting: {err}"), Some(EXIT_ERROR))
+        }
+    } else {
+        match serde_json::from_str(&contents) {
+            Ok(creds) => creds,
+            Err(err) => exit_with_error_msg(&format!("json file has invalid formatting: {err}"), Some(EXIT_ERROR))
+        }
     };
     creds
 }
 
-async fn handle_clip_subcommand(args: ClipCommandArgs) {
-    let re = Regex::new(r"(?:https?://(?:www\.)?twitch\.tv/[^/]+/clip/|https?://clips\.twitch\.tv/)?([A-Za-z0-9_-]+)")
-        .expect("Failed to parse regex string");
+async fn authenticate(client: &reqwest::Client, creds: Option<String>, auth: AuthMode) -> TwitchSession {
+    let creds = load_credentials(creds).await;
+    let session = match auth {
+        AuthMode::App => TwitchSession::new(client.clone(), &creds.client_id, &creds.client_secret).await,
+        AuthMode::User => TwitchSession::with_user_token(client.clone(), &creds.client_id, &creds.client_secret, |uri, code| {
+            eprintln!("To authorize twdl, open {uri} and enter the code {code}");
+        }).await
+    };
+    match session {
+        Ok(session) => session,
+        Err(err) => exit_with_error_msg(&err.to_string(), Some(EXIT_ERROR))
+    }
+}
+
+async fn fetch_clip(client: &reqwest::Client, creds: Option<String>, auth: AuthMode, slug: &str) -> Option<Clip> {
+    let session = authenticate(client, creds, auth).await;
+    match twdl::twitch_utils::get_clip(slug, &session).await {
+        Ok(clip) => clip,
+        Err(err) => {
+            error!("Failed to fetch clip info: {err}");
+            None
+        }
+    }
+}
+
+async fn write_playlist(path: &str, entries: &[PlaylistEntry]) {
+    match write_m3u(Path::new(path), entries).await {
+        Ok(_) => info!("Wrote playlist with {} entries to {path}", entries.len()),
+        Err(err) => exit_with_error_msg(&format!("Failed to write playlist: {err}"), Some(EXIT_ERROR))
+    }
+}
+
+async fn write_failures_log(path: &str, failed: &[FailedClip]) {
+    match write_failures(Path::new(path), failed).await {
+        Ok(_) => info!("Wrote {} failed clips to {path}", failed.len()),
+        Err(err) => error!("Failed to write failures log: {err}")
+    }
+}
 
-    let path = match PathBuf::from_str(&args.output) {
+// Returns the end to trim at. `duration` comes from GQL or helix, an end past it is dropped so the trim
+// runs to the clip's real end instead of leaving it to ffmpeg.
+fn validate_trim(start: Option<f64>, end: Option<f64>, duration: Option<f64>) -> Option<f64> {
+    if matches!((start, end), (Some(start), Some(end)) if start >= end) {
+        exit_with_error_msg("--trim-start must be before --trim-end", Some(EXIT_ERROR));
+    }
+    let Some(duration) = duration else {
+        warn!("Can't check the trim offsets, the clip's duration isn't known");
+        return end;
+    };
+    if let Some(start) = start.filter(|start| *start >= duration) {
+        exit_with_error_msg(&format!("--trim-start {start}s is past the end of the {duration}s clip"), Some(EXIT_ERROR));
+    }
+    match end {
+        Some(end) if end > duration => {
+            warn!("--trim-end {end}s is past the end of the {duration}s clip, trimming to the end instead");
+            None
+        }
+        end => end
+    }
+}
+
+fn source_file_json(slug: &str, file: &SourceFile) -> serde_json::Value {
+    serde_json::json!({
+        "slug": slug,
+        "quality": file.quality,
+        "frame_rate": file.frame_rate,
+        "url": file.url.as_str()
+    })
+}
+
+async fn handle_clip_subcommand(args: ClipCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions) {
+    let options = &DownloadOptions {
+        overwrite_metadata: args.overwrite_metadata,
+        pretty_metadata: !args.compact_metadata,
+        checksums: args.checksums,
+        ..options.clone()
+    };
+    // `-o -` is the same as --stdout, which clap already keeps apart from the options below
+    let stdout = args.stdout || args.output.as_deref() == Some("-");
+    if stdout && (args.link || args.metadata || args.audio_only || args.checksums || args.all_qualities || args.trim_start.is_some() || args.trim_end.is_some()) {
+        exit_with_error_msg("-o - can't be combined with --link, --metadata, --audio-only, --checksums, --all-qualities or trimming", Some(EXIT_ERROR));
+    }
+    let path = match PathBuf::from_str(args.output.as_deref().unwrap_or(".")) {
         Ok(path) => path,
-        Err(_) => exit_with_error_msg("Invalid output path", Some(1))
+        Err(_) => exit_with_error_msg("Invalid output path", Some(EXIT_ERROR))
     };
 
-    let slug = if let Some(caps) = re.captures(&args.clip) {
-        if let Some(m) = caps.get(1) {
-            m.as_str().to_string()
-        } else {
-            exit_with_error_msg("No clip slug found in URL", Some(1))
-        }
-    } else {
-        exit_with_error_msg("Invalid Clip URL format", Some(1))
+    let slug = match twdl::extract_clip_slug(&args.clip) {
+        Some(slug) => slug,
+        None => exit_with_error_msg("Invalid Clip URL format", Some(EXIT_INVALID_CLIP))
     };
 
-    let files = match get_video_source_files(&slug).await {
-        Ok(files) => files,
-        Err(_) => exit_with_error_msg(&format!("Failed to get clips for slug {slug}"), Some(1))
+    // Checked before resolving the clip so a missing login doesn't cost a request first
+    let has_credentials = args.credentials.is_some() || credentials_from_env().or_else(credentials_from_keyring).is_some();
+    if args.metadata && !args.link && !has_credentials {
+        exit_with_error_msg("metadata requires twitch credentials to be provided", Some(EXIT_ERROR));
+    }
+
+    let SourceInfo { duration_ms, files } = match get_video_source_info(client, &slug, gql).await {
+        Ok(info) => info,
+        Err(TwdlError::NoSourceFiles(_)) => exit_with_error_msg("No Source files found", Some(EXIT_ERROR)),
+        Err(err @ TwdlError::ClipUnavailable { .. }) => exit_with_error_msg(&format!("{err}, check the URL. Deleted, subscriber-only and region-locked clips can't be downloaded"), Some(EXIT_CLIP_UNAVAILABLE)),
+        Err(TwdlError::NoQualities(_)) => exit_with_error_msg("Clip has no available qualities, it may be deleted or still processing", Some(EXIT_CLIP_UNAVAILABLE)),
+        Err(err @ TwdlError::InvalidSlug(_)) => exit_with_error_msg(&err.to_string(), Some(EXIT_INVALID_CLIP)),
+        Err(err) => exit_with_error_msg(&format!("Failed to get clips for slug {slug}: {err}"), Some(EXIT_ERROR))
     };
 
-    let best = match files.iter().max() {
+    if let Err(err) = twdl::check_min_quality(&files, options.min_quality, &slug) {
+        exit_with_error_msg(&err.to_string(), Some(EXIT_ERROR));
+    }
+    let best = match twdl::select_source_file(&files, options.quality) {
         Some(best) => best,
-        None => exit_with_error_msg("No Source files found", Some(1))
+        None => exit_with_error_msg("No Source files found", Some(EXIT_ERROR))
     };
 
     if args.link {
-        println!("{}", best.url.clone().as_str());
-    } else {
-        if args.metadata {
-            let creds = match args.credentials {
-                Some(creds) => load_credentials(creds).await,
-                None => exit_with_error_msg("metadata requires twitch credentials to be provided", Some(1))
-            };
-            let token = match twdl::twitch_utils::get_token(&creds.client_id, &creds.client_secret).await {
-                Ok(token) => token,
-                Err(_) => {
-                    exit_with_error_msg("Failed to fetch token from twitch", Some(1));
+        if args.all_qualities {
+            let mut sorted: Vec<&SourceFile> = files.iter().collect();
+            sorted.sort_by(|a, b| (b.quality, b.frame_rate).cmp(&(a.quality, a.frame_rate)));
+            match args.format {
+                LinkFormat::Plain => {
+                    for file in sorted {
+                        println!("{} {} {}", file.quality, file.frame_rate, file.url.as_str());
+                    }
+                }
+                LinkFormat::Json => {
+                    let files: Vec<serde_json::Value> = sorted.into_iter().map(|file| source_file_json(&slug, file)).collect();
+                    println!("{}", serde_json::Value::Array(files));
                 }
+            }
+        } else {
+            match args.format {
+                LinkFormat::Plain => println!("{}", best.url.clone().as_str()),
+                LinkFormat::Json => println!("{}", source_file_json(&slug, best))
+            }
+        }
+        if let Some(m3u) = &args.m3u {
+            // Without credentials there is no title or duration to show, fall back to the slug
+            let clip = match has_credentials {
+                true => fetch_clip(client, args.credentials, args.auth, &slug).await,
+                false => None
+            };
+            let entry = match clip {
+                Some(clip) => PlaylistEntry::from_clip(&clip, best),
+                None => PlaylistEntry { title: slug.clone(), duration: -1, url: best.url.to_string() }
             };
-            let clip = twdl::twitch_utils::get_clip(&slug, &token).await;
-            if let Ok(Some(clip)) = clip {
-                twdl::save_metadata(&clip, &path).await;
+            write_playlist(m3u, &[entry]).await;
+        }
+    } else if stdout {
+        // Not retried, whatever was already piped out can't be taken back
+        if let Err(err) = twdl::download_to(client, &slug, best.url.clone(), &mut tokio::io::stdout(), options).await {
+            exit_with_error_msg(&format!("Failed to download clip {slug}: {err}"), Some(EXIT_ERROR));
+        }
+    } else {
+        let trimming = args.trim_start.is_some() || args.trim_end.is_some();
+        // GQL usually already gave the duration, helix is only asked for it when it didn't
+        let clip = match (args.metadata || (trimming && duration_ms.is_none())) && has_credentials {
+            true => fetch_clip(client, args.credentials, args.auth, &slug).await,
+            false => None
+        };
+        let trim_end = match trimming {
+            true => {
+                let duration = clip.as_ref().map(|clip| clip.duration)
+                    .or(duration_ms.map(|duration_ms| duration_ms as f64 / 1000.0));
+                validate_trim(args.trim_start, args.trim_end, duration)
             }
+            false => None
+        };
+        if let Some(clip) = clip.as_ref().filter(|_| args.metadata) {
+            twdl::save_metadata(clip, Some(best), &path, options).await;
+        }
+        if args.all_qualities {
+            download_all_qualities(client, &slug, &files, &path, gql, options).await;
+            return;
+        }
+        let clip_path = &twdl::clip_path(&path, &slug);
+        if let Err(err) = twdl::download_source(client, &slug, best, clip_path, gql, options).await {
+            exit_with_error_msg(&format!("Failed to download clip {slug}: {err}"), Some(EXIT_ERROR));
+        }
+        if trimming {
+            let trimmed = twdl::clip_path(&path, &format!("{slug}_trimmed"));
+            match trim(clip_path, &trimmed, args.trim_start.unwrap_or(0.0), trim_end).await {
+                Ok(()) => info!("Wrote trimmed clip to {}", trimmed.display()),
+                Err(err) => error!("Failed to trim clip {slug}: {err}")
+            }
+        }
+        if args.audio_only {
+            twdl::extract_audio(&slug, clip_path.clone(), &args.audio_format, args.delete_video).await;
         }
-        let clip_path = &path.join(PathBuf::from_str(&format!("{}.mp4", &slug)).unwrap());
-        twdl::download_file(best.url.clone(), &clip_path).await;
     }
     
 
 }
 
-async fn handle_channel_subcommand(args: ChannelCommandArgs, multi: Arc<MultiProgress>) -> () {
-    let creds = load_credentials(args.credentials).await;
-    let token = match twdl::twitch_utils::get_token(&creds.client_id, &creds.client_secret).await {
-        Ok(token) => token,
-        Err(err) => exit_with_error_msg(&format!("Failed to fetch application token: {err}"), Some(1))
+// Each distinct quality and frame rate of a clip, side by side in `directory`
+async fn download_all_qualities(client: &reqwest::Client, slug: &str, files: &[SourceFile], directory: &Path, gql: &GqlConfig, options: &DownloadOptions) {
+    let mut seen = HashSet::new();
+    let files: Vec<&SourceFile> = files.iter().filter(|file| seen.insert((file.quality, file.frame_rate))).collect();
+    let results: Vec<_> = stream::iter(files)
+        .map(|file| async move {
+            let path = twdl::clip_path(directory, &format!("{slug}-{}p{}", file.quality, file.frame_rate));
+            let result = twdl::download_source(client, slug, file, &path, gql, options).await;
+            (path, result)
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+
+    let mut written = Vec::new();
+    for (path, result) in results {
+        match result {
+            Ok(()) => written.push(path),
+            Err(err) => error!("Failed to download {}: {err}", path.display())
+        }
+    }
+    written.sort();
+    for path in &written {
+        info!("Wrote {}", path.display());
+    }
+    if written.is_empty() {
+        exit_with_error_msg(&format!("Failed to download clip {slug}"), Some(EXIT_ERROR));
+    }
+}
+
+async fn handle_info_subcommand(args: InfoCommandArgs, client: &reqwest::Client) {
+    let slug = match twdl::extract_clip_slug(&args.clip) {
+        Some(slug) => slug,
+        None => exit_with_error_msg("Invalid Clip URL format", Some(EXIT_INVALID_CLIP))
     };
-    let id = login_or_id(&args.broadcaster_id, &args.broadcaster_login, &token).await;
-    let user = match twdl::twitch_utils::get_user(&id, &token).await {
+
+    let clip = match fetch_clip(client, args.credentials, AuthMode::App, &slug).await {
+        Some(clip) => clip,
+        None => exit_with_error_msg(&format!("No clip found for slug {slug}"), Some(EXIT_CLIP_UNAVAILABLE))
+    };
+
+    if args.json {
+        match serde_json::to_string_pretty(&clip) {
+            Ok(json) => println!("{json}"),
+            Err(err) => exit_with_error_msg(&format!("Failed to serialize clip: {err}"), Some(EXIT_ERROR))
+        }
+        return;
+    }
+
+    println!("Title:       {}", clip.title);
+    println!("Broadcaster: {}", clip.broadcaster_name);
+    println!("Creator:     {}", clip.creator_name);
+    println!("Created:     {}", clip.created_at);
+    println!("Views:       {}", clip.view_count);
+    println!("Duration:    {:.1}s", clip.duration);
+    println!("URL:         {}", clip.url);
+}
+
+async fn handle_user_subcommand(args: UserCommandArgs, client: &reqwest::Client) {
+    let session = authenticate(client, args.credentials, AuthMode::App).await;
+    let id = login_or_id(&args.broadcaster_id, &args.broadcaster_login, &session).await;
+    let user = match twdl::twitch_utils::get_user(&id, &session).await {
         Ok(Some(user)) => user,
-        _ => exit_with_error_msg("Failed to get user info", Some(1))
+        Ok(None) => exit_with_error_msg(&format!("No user found with id {id}"), Some(EXIT_ERROR)),
+        Err(err) => exit_with_error_msg(&format!("Failed to get user info: {err}"), Some(EXIT_ERROR))
     };
 
-    // Use user defined dates or default to range between broadcaster signup date and now
-    let start = match args.start_timestamp {
-        Some(str) => interpret_date(str),
-        None => twdl::twitch_utils::convert_ts(&user.created_at),
+    if args.json {
+        match serde_json::to_string_pretty(&user) {
+            Ok(json) => println!("{json}"),
+            Err(err) => exit_with_error_msg(&format!("Failed to serialize user: {err}"), Some(EXIT_ERROR))
+        }
+        return;
+    }
+
+    println!("Display name: {}", user.display_name);
+    println!("Login:        {}", user.login);
+    println!("ID:           {}", user.id);
+    println!("Description:  {}", user.description.as_deref().unwrap_or(""));
+    println!("Created:      {}", user.created_at);
+    // Twitch no longer updates view counts but still returns the field
+    #[allow(deprecated)]
+    let view_count = user.view_count;
+    println!("Views:        {view_count}");
+}
+
+// Broadcasters named by -i, -l and --broadcasters-file, with the name used for their output folder
+async fn channel_broadcasters(args: &ChannelCommandArgs, session: &TwitchSession) -> Vec<(String, UserId)> {
+    if let Some(id) = args.broadcaster_id {
+        return vec![(id.to_string(), id.to_string().into())];
+    }
+
+    let mut logins = args.broadcaster_login.clone();
+    if let Some(file) = &args.broadcasters_file {
+        let contents = match read(file).await {
+            Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(err) => exit_with_error_msg(&format!("Failed to read broadcasters file: {err}"), Some(EXIT_ERROR))
+        };
+        logins.extend(contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string));
+    }
+    if logins.is_empty() {
+        exit_with_error_msg("Either broadcaster login or id is required", Some(EXIT_ERROR));
+    }
+
+    let mut broadcasters = Vec::with_capacity(logins.len());
+    for login in logins {
+        match twdl::twitch_utils::get_broadcaster_id(&login, session).await {
+            Ok(Some(id)) => broadcasters.push((login, id)),
+            _ => error!("Error finding user with login {login}")
+        }
+    }
+    broadcasters
+}
+
+async fn handle_channel_subcommand(args: ChannelCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions, multi: Arc<MultiProgress>) -> () {
+    let started_at = Utc::now();
+    let base_path = match PathBuf::from_str(args.output.as_deref().unwrap_or(".")) {
+        Ok(path) => path,
+        Err(_) => exit_with_error_msg("Invalid path", Some(EXIT_ERROR))
     };
-    let end = match args.end_timestamp {
-        Some(str) => interpret_date(str),
-        None => Utc::now()
+    let options = DownloadOptions {
+        concurrency: args.chunk_size.unwrap_or(10),
+        metadata: args.metadata,
+        overwrite_metadata: args.overwrite_metadata,
+        pretty_metadata: !args.compact_metadata,
+        sync: args.sync,
+        prescan: args.prescan,
+        number: args.number,
+        checksums: args.checksums,
+        thumbnails: args.thumbnails,
+        embed_metadata: args.embed_metadata,
+        nfo: args.nfo,
+        audio_format: args.audio_only.then(|| args.audio_format.clone()),
+        delete_video: args.delete_video,
+        gif: gif_options(args.gif, args.gif_duration, args.gif_width, args.gif_fps).await,
+        ..options.clone()
     };
+    let link_mode = args.link || args.m3u.is_some();
+    let exec = args.exec.clone().map(|template| Arc::new(ExecHook::new(template, args.exec_concurrency)));
 
-    let output_path = match PathBuf::from_str(&args.output) {
-        Ok(path) => path,
-        Err(_) => exit_with_error_msg("Invalid path", Some(1))
+    if args.batch.is_some() {
+        if let Some(report) = download_channel_batch(&args, client, gql, &options, &multi, exec.as_ref()).await {
+            finish_channel_run(&args, client, vec![(String::from("batch"), report)], false, started_at).await;
+        }
+        return;
+    }
+
+    let session = authenticate(client, args.credentials.clone(), args.auth).await;
+    let broadcasters = channel_broadcasters(&args, &session).await;
+    if broadcasters.is_empty() {
+        exit_with_error_msg("No broadcasters found", Some(EXIT_ERROR));
+    }
+    // With several broadcasters each gets a folder named after their login
+    let grouped = broadcasters.len() > 1;
+
+    let mut link_clips = Vec::new();
+    let mut reports = Vec::new();
+    for (name, id) in broadcasters {
+        let user = match twdl::twitch_utils::get_user(&id, &session).await {
+            Ok(Some(user)) => user,
+            _ if grouped => {
+                error!("Failed to get user info for {name}");
+                continue;
+            }
+            _ => exit_with_error_msg("Failed to get user info", Some(EXIT_ERROR))
+        };
+
+        let output_path = match grouped {
+            true => base_path.join(&name),
+            false => base_path.clone()
+        };
+        let created = match grouped && !link_mode {
+            true => tokio::fs::create_dir_all(&output_path).await,
+            false => Ok(())
+        };
+        if let Err(err) = created {
+            error!("Failed to create {}: {err}", output_path.display());
+            continue;
+        }
+
+        // Default to range between broadcaster signup date and now
+        let (start, end) = match (last_run_start(args.since_last_run, &output_path).await, args.since) {
+            (Some(start), _) => (start, args.end_timestamp.clone().map(interpret_date).unwrap_or_else(Utc::now)),
+            (None, Some(since)) => since_window(since, Utc::now()),
+            (None, None) => interpret_datetimes(args.start_timestamp.clone(), args.end_timestamp.clone(), twdl::twitch_utils::convert_ts(&user.created_at))
+        };
+        let chunking_type = twdl::twitch_utils::DateChunkingType::ByDuration(TimeDelta::weeks(4));
+        let clips = twdl::twitch_utils::get_clips_chunked(&ClipSource::Broadcaster(id), &session, start, end, chunking_type, Some(100), Duration::from_millis(args.page_delay)).await;
+        info!("Fetched {} clips for {name}", clips.len());
+        let clips = filter_created(clips, &args.created_after, &args.created_before);
+        let clips = match args.interactive {
+            true => select_clips(clips, &multi),
+            false => clips
+        };
+        if link_mode {
+            link_clips.extend(clips);
+            continue;
+        }
+        confirm_downloads(&clips, args.max_downloads, args.yes, &multi);
+
+        let mut report = download_clips(Some(multi.clone()),
+            client,
+            clips,
+            &output_path,
+            gql,
+            &options,
+            exec.as_ref().map(exec_callback).as_ref()
+        ).await;
+        collect_exec_failures(&mut report, exec.as_deref());
+        reports.push((name, report));
+    }
+
+    if link_mode {
+        output_source_links(&link_clips, client, gql, &options, &multi, args.link, args.m3u.as_deref()).await;
+        return;
+    }
+
+    finish_channel_run(&args, client, reports, grouped, started_at).await;
+}
+
+// Summaries, notifications and logs once every broadcaster's clips are downloaded
+async fn finish_channel_run(args: &ChannelCommandArgs, client: &reqwest::Client, reports: Vec<(String, DownloadReport)>, grouped: bool, started_at: DateTime<Utc>) {
+    for (name, report) in &reports {
+        match grouped {
+            true => info!("{name}: downloaded {} clips, {} unavailable, {} failed ({})", report.downloaded.len(), report.unavailable.len(), report.failed.len(), report.throughput()),
+            false => info!("Downloaded {} clips, {} unavailable, {} failed ({})", report.downloaded.len(), report.unavailable.len(), report.failed.len(), report.throughput())
+        }
+    }
+    report_gifs(reports.iter().map(|(_, report)| report));
+    report_exec_failures(reports.iter().map(|(_, report)| report));
+    if let Some(concat) = &args.concat {
+        let files: Vec<PathBuf> = reports.iter().flat_map(|(_, report)| &report.downloaded).map(|clip| clip.path.clone()).collect();
+        concat_clips(concat, &files, args.concat_only).await;
+    }
+    if let Some(webhook) = &args.discord_webhook {
+        for (name, report) in &reports {
+            // Only a notification, the run itself already finished
+            if let Err(err) = notify_discord(client, webhook, name, report).await {
+                warn!("Failed to post to the Discord webhook: {err}");
+            }
+        }
+    }
+    if args.json {
+        let summaries: Vec<RunSummary> = reports.iter().map(|(name, report)| RunSummary::new(name, started_at, report)).collect();
+        print_json_summary(&summaries);
+    }
+    if let Some(webhook) = &args.webhook {
+        for (name, report) in &reports {
+            post_webhook(client, webhook, &args.webhook_headers, &RunSummary::new(name, started_at, report)).await;
+        }
+    }
+    if let Some(failures) = &args.failures {
+        let failed: Vec<FailedClip> = reports.into_iter().flat_map(|(_, report)| report.failed).collect();
+        write_failures_log(failures, &failed).await;
+    }
+}
+
+// Options that need a clip's helix details, which a batch run without credentials can't look up
+fn batch_flag_needing_credentials(args: &ChannelCommandArgs) -> Option<&'static str> {
+    [
+        (args.metadata, "--metadata"),
+        (args.thumbnails, "--thumbnails"),
+        (args.nfo, "--nfo"),
+        (args.embed_metadata, "--embed-metadata"),
+        (args.audio_only, "--audio-only"),
+        (args.gif, "--gif"),
+        (args.sync, "--sync"),
+        (args.number, "--number"),
+        (args.exec.is_some(), "--exec"),
+        (args.m3u.is_some(), "--m3u")
+    ].into_iter().find(|(used, _)| *used).map(|(_, flag)| flag)
+}
+
+// --batch: helix is only asked for the clips' details when there are credentials to ask with.
+// None in link mode, where nothing is downloaded.
+async fn download_channel_batch(args: &ChannelCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions, multi: &Arc<MultiProgress>, exec: Option<&Arc<ExecHook>>) -> Option<DownloadReport> {
+    let path = args.batch.as_deref().unwrap_or_default();
+    let contents = match read(path).await {
+        Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+        Err(err) => exit_with_error_msg(&format!("Failed to read batch file: {err}"), Some(EXIT_ERROR))
     };
+    let mut slugs = Vec::new();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        match twdl::extract_clip_slug(line) {
+            Some(slug) => slugs.push(slug),
+            None => warn!("Skipping {line}, it isn't a clip URL or slug")
+        }
+    }
+    let output_path = PathBuf::from(args.output.as_deref().unwrap_or("."));
+
+    let has_credentials = args.credentials.is_some() || credentials_from_env().or_else(credentials_from_keyring).is_some();
+    if !has_credentials {
+        if let Some(flag) = batch_flag_needing_credentials(args) {
+            exit_with_error_msg(&format!("{flag} needs twitch credentials to look up each clip's details"), Some(EXIT_ERROR));
+        }
+        if args.link {
+            print_slug_links(&slugs, client, gql, options).await;
+            return None;
+        }
+        // Without helix there's no duration to estimate the size from
+        confirm_count(slugs.len(), &format!("{} clips", slugs.len()), args.max_downloads, args.yes, multi);
+        return Some(twdl::download_slugs(Some(multi.as_ref()), client, &slugs, &output_path, gql, options).await);
+    }
+
+    let session = authenticate(client, args.credentials.clone(), args.auth).await;
+    let mut clips = Vec::with_capacity(slugs.len());
+    let mut missing = Vec::new();
+    for slug in slugs {
+        match twdl::twitch_utils::get_clip(&slug, &session).await {
+            Ok(Some(clip)) => clips.push(clip),
+            Ok(None) => {
+                warn!("Skipping clip {slug}, twitch has no clip with that slug");
+                missing.push(slug);
+            }
+            Err(err) => {
+                error!("Failed to fetch clip info for {slug}: {err}");
+                missing.push(slug);
+            }
+        }
+    }
+    if args.link || args.m3u.is_some() {
+        output_source_links(&clips, client, gql, options, multi, args.link, args.m3u.as_deref()).await;
+        return None;
+    }
+    confirm_downloads(&clips, args.max_downloads, args.yes, multi);
+    let mut report = download_clips(Some(multi.clone()), client, clips, &output_path, gql, options, exec.map(exec_callback).as_ref()).await;
+    report.unavailable.extend(missing);
+    collect_exec_failures(&mut report, exec.map(Arc::as_ref));
+    Some(report)
+}
+
+// --link for a batch without credentials, in the order the file lists the clips
+async fn print_slug_links(slugs: &[String], client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions) {
+    let results: Vec<_> = stream::iter(slugs)
+        .map(|slug| async move { (slug, get_video_source_files(client, slug, gql).await) })
+        .buffered(options.concurrency)
+        .collect()
+        .await;
+    for (slug, result) in results {
+        match result.map(|files| twdl::select_source_file(&files, options.quality).cloned()) {
+            Ok(Some(file)) => println!("{}", file.url.as_str()),
+            Ok(None) => error!("No source files found for clip {slug}"),
+            Err(err) => error!("Failed to get the source of clip {slug}: {err}")
+        }
+    }
+}
+
+// Each broadcaster runs in turn, sharing the session and client
+async fn handle_channels_subcommand(args: ChannelsCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions, multi: Arc<MultiProgress>) {
+    let started_at = Utc::now();
+    let batch = match load_batch(Path::new(&args.file)).await {
+        Ok(batch) => batch,
+        Err(err) => exit_with_error_msg(&format!("Failed to read {}: {err}", args.file), Some(EXIT_ERROR))
+    };
+    // Checked before anything is downloaded, a bad date in a later entry would otherwise stop the run halfway
+    for channel in &batch.channels {
+        if channel.start.is_none() && channel.end.is_some() {
+            exit_with_error_msg(&format!("{}: an end time requires a start time", channel.login), Some(EXIT_ERROR));
+        }
+        validate_date_range(channel.start.as_ref(), channel.end.as_ref());
+        validate_created_range(channel.created_after.as_ref(), channel.created_before.as_ref());
+    }
+    let session = authenticate(client, args.credentials.clone(), AuthMode::App).await;
+    let options = DownloadOptions {
+        concurrency: args.chunk_size.unwrap_or(10),
+        metadata: args.metadata,
+        sync: args.sync,
+        ..options.clone()
+    };
+
+    let mut reports = Vec::new();
+    let mut skipped = Vec::new();
+    for channel in batch.channels {
+        match download_batch_channel(&channel, &args, client, &session, gql, &options, &multi).await {
+            Ok(report) => {
+                info!("{}: downloaded {} clips, {} unavailable, {} failed ({})", channel.login, report.downloaded.len(), report.unavailable.len(), report.failed.len(), report.throughput());
+                reports.push((channel.login, report));
+            }
+            Err(err) => {
+                error!("{err}");
+                skipped.push((channel.login, err));
+            }
+        }
+    }
+
+    let total = reports.iter().map(|(_, report)| report.downloaded.len()).sum::<usize>();
+    let failed = reports.iter().map(|(_, report)| report.failed.len()).sum::<usize>();
+    info!("Downloaded {total} clips for {} broadcasters, {failed} failed", reports.len());
+    if !skipped.is_empty() {
+        let logins: Vec<&str> = skipped.iter().map(|(login, _)| login.as_str()).collect();
+        error!("Skipped {} broadcasters: {}", skipped.len(), logins.join(", "));
+    }
+    if args.json {
+        // Skipped broadcasters are listed with their error and nothing downloaded
+        let empty = DownloadReport::default();
+        let summaries: Vec<RunSummary> = reports.iter()
+            .map(|(name, report)| RunSummary::new(name, started_at, report))
+            .chain(skipped.iter().map(|(name, err)| RunSummary { error: Some(err.clone()), ..RunSummary::new(name, started_at, &empty) }))
+            .collect();
+        print_json_summary(&summaries);
+    }
+    if !skipped.is_empty() {
+        process::exit(EXIT_ERROR);
+    }
+}
+
+// Err when the broadcaster couldn't be looked up or their folder created, the rest of the batch still runs
+async fn download_batch_channel(channel: &BatchChannel, args: &ChannelsCommandArgs, client: &reqwest::Client, session: &TwitchSession, gql: &GqlConfig, options: &DownloadOptions, multi: &Arc<MultiProgress>) -> Result<DownloadReport, String> {
+    let login = &channel.login;
+    let id = match twdl::twitch_utils::get_broadcaster_id(login, session).await {
+        Ok(Some(id)) => id,
+        _ => return Err(format!("Error finding user with login {login}"))
+    };
+    let user = match twdl::twitch_utils::get_user(&id, session).await {
+        Ok(Some(user)) => user,
+        _ => return Err(format!("Failed to get user info for {login}"))
+    };
+    let output_path = match &channel.output {
+        Some(output) => PathBuf::from(expand_path(output)),
+        None => PathBuf::from(args.output.as_deref().unwrap_or(".")).join(login)
+    };
+    if let Err(err) = tokio::fs::create_dir_all(&output_path).await {
+        return Err(format!("Failed to create {}: {err}", output_path.display()));
+    }
+
+    let (start, end) = interpret_datetimes(channel.start.clone(), channel.end.clone(), twdl::twitch_utils::convert_ts(&user.created_at));
     let chunking_type = twdl::twitch_utils::DateChunkingType::ByDuration(TimeDelta::weeks(4));
-    let clips = twdl::twitch_utils::get_clips_chunked(&id, &token, start, end, chunking_type, Some(100)).await;
+    let clips = twdl::twitch_utils::get_clips_chunked(&ClipSource::Broadcaster(id), session, start, end, chunking_type, Some(100), Duration::from_millis(args.page_delay)).await;
+    info!("Fetched {} clips for {login}", clips.len());
+    let mut clips = filter_created(clips, &channel.created_after, &channel.created_before);
+    if let Some(limit) = channel.limit {
+        clips.sort_by(|a, b| b.view_count.cmp(&a.view_count));
+        clips.truncate(limit);
+    }
+
+    Ok(download_clips(Some(multi.clone()), client, clips, &output_path, gql, options, None).await)
+}
+
+async fn handle_game_subcommand(args: GameCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions, multi: Arc<MultiProgress>) {
+    let started_at = Utc::now();
+    let session = authenticate(client, args.credentials, AuthMode::App).await;
+    let id = game_name_or_id(&args.game_id, &args.game_name, &session).await;
+    let game = id.to_string();
+
+    let output_path = match PathBuf::from_str(args.output.as_deref().unwrap_or(".")) {
+        Ok(path) => path,
+        Err(_) => exit_with_error_msg("Invalid path", Some(EXIT_ERROR))
+    };
+
+    let (start, end) = match last_run_start(args.since_last_run, &output_path).await {
+        Some(start) => (start, args.end_timestamp.map(interpret_date).unwrap_or_else(Utc::now)),
+        None => interpret_datetimes(args.start_timestamp, args.end_timestamp, Utc::now() - TimeDelta::weeks(1))
+    };
+    let chunking_type = twdl::twitch_utils::DateChunkingType::ByDuration(TimeDelta::weeks(1));
+    let clips = twdl::twitch_utils::get_clips_chunked(&ClipSource::Game(id), &session, start, end, chunking_type, Some(100), Duration::from_millis(args.page_delay)).await;
+    let mut clips = filter_created(clips, &args.created_after, &args.created_before);
+
+    // Each date chunk is ordered by views on its own, re-sort the merged list before cutting it
+    clips.sort_by(|a, b| b.view_count.cmp(&a.view_count));
+    if let Some(limit) = args.limit {
+        clips.truncate(limit);
+    }
     info!("Fetched {} clips, starting download", clips.len());
-    if args.link {
-        let mut source_file_futures = Vec::new();
-        for clip in &clips {
-            source_file_futures.push(get_video_source_files(&clip.id));
-        }
-        let source_file_results = join_all(source_file_futures).await;
-        for result in &source_file_results {
-            let files = match result {
-                Ok(files) => files,
-                Err(_) => {
-                    error!("Error fetching source URL");
-                    continue;
-                }
-            };
-            let url = match files.iter().max() {
-                Some(best) => &best.url,
-                None => {
-                    error!("Could not find any source files for clip");
-                    continue;
-                }
-            };
-            println!("{}", url.as_str())
-        }
+    let options = DownloadOptions {
+        concurrency: args.chunk_size.unwrap_or(10),
+        metadata: args.metadata,
+        overwrite_metadata: args.overwrite_metadata,
+        pretty_metadata: !args.compact_metadata,
+        sync: args.sync,
+        prescan: args.prescan,
+        number: args.number,
+        checksums: args.checksums,
+        thumbnails: args.thumbnails,
+        embed_metadata: args.embed_metadata,
+        nfo: args.nfo,
+        audio_format: args.audio_only.then(|| args.audio_format.clone()),
+        delete_video: args.delete_video,
+        gif: gif_options(args.gif, args.gif_duration, args.gif_width, args.gif_fps).await,
+        ..options.clone()
+    };
+    if args.link || args.m3u.is_some() {
+        output_source_links(&clips, client, gql, &options, &multi, args.link, args.m3u.as_deref()).await;
     } else {
-        download_clips(multi, 
-            clips, 
-            &output_path, 
-            args.chunk_size.unwrap_or(10), 
-            args.link,
-            args.metadata
+        confirm_downloads(&clips, args.max_downloads, args.yes, &multi);
+        let exec = args.exec.map(|template| Arc::new(ExecHook::new(template, args.exec_concurrency)));
+        let mut report = download_clips(Some(multi),
+            client,
+            clips,
+            &output_path,
+            gql,
+            &options,
+            exec.as_ref().map(exec_callback).as_ref()
         ).await;
+        collect_exec_failures(&mut report, exec.as_deref());
+        info!("Downloaded {} clips, {} unavailable, {} failed ({})", report.downloaded.len(), report.unavailable.len(), report.failed.len(), report.throughput());
+        report_gifs([&report]);
+        report_exec_failures([&report]);
+        if let Some(concat) = &args.concat {
+            let files: Vec<PathBuf> = report.downloaded.iter().map(|clip| clip.path.clone()).collect();
+            concat_clips(concat, &files, args.concat_only).await;
+        }
+        if let Some(failures) = &args.failures {
+            write_failures_log(failures, &report.failed).await;
+        }
+        if args.json {
+            print_json_summary(&[RunSummary::new(&game, started_at, &report)]);
+        }
+        if let Some(webhook) = &args.webhook {
+            post_webhook(client, webhook, &args.webhook_headers, &RunSummary::new(&game, started_at, &report)).await;
+        }
+    }
+}
+
+// Falls back to every clip when there's no terminal to ask on
+fn select_clips(clips: Vec<Clip>, multi: &MultiProgress) -> Vec<Clip> {
+    if clips.is_empty() {
+        return clips;
+    }
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        warn!("--interactive needs a terminal, downloading all {} clips", clips.len());
+        return clips;
+    }
+    let items: Vec<String> = clips.iter()
+        .map(|clip| format!(
+            "{} ({} views, {})",
+            clip.title,
+            clip.view_count,
+            clip.created_at.as_str().get(..10).unwrap_or(clip.created_at.as_str())
+        ))
+        .collect();
+    // Progress bars drawn over the list would garble it
+    let selection = multi.suspend(|| MultiSelect::new()
+        .with_prompt("Select clips to download (space to toggle, enter to confirm)")
+        .items(&items)
+        .interact_opt());
+    match selection {
+        Ok(Some(chosen)) => {
+            let chosen: HashSet<usize> = chosen.into_iter().collect();
+            clips.into_iter().enumerate().filter(|(i, _)| chosen.contains(i)).map(|(_, clip)| clip).collect()
+        }
+        Ok(None) => {
+            info!("Selection cancelled, skipping these clips");
+            Vec::new()
+        }
+        Err(err) => {
+            warn!("Failed to show the clip list ({err}), downloading all {} clips", clips.len());
+            clips
+        }
+    }
+}
+
+// Rough average of twitch's source qualities, only used to warn about how much a run would download
+const ESTIMATED_BYTES_PER_SECOND: f64 = 750_000.0;
+
+fn estimated_size(clips: &[Clip]) -> u64 {
+    (clips.iter().map(|clip| clip.duration).sum::<f64>() * ESTIMATED_BYTES_PER_SECOND) as u64
+}
+
+// Runs with more clips than this ask before starting, when there's a terminal to ask on
+const CONFIRM_THRESHOLD: usize = 500;
+
+// Exits when the clips go over `max_downloads`, or the user declines a large download, unless `yes` is set
+fn confirm_downloads(clips: &[Clip], max_downloads: Option<usize>, yes: bool, multi: &MultiProgress) {
+    let summary = format!("{} clips (about {})", clips.len(), HumanBytes(estimated_size(clips)));
+    confirm_count(clips.len(), &summary, max_downloads, yes, multi);
+}
+
+// `summary` describes the `count` clips in the messages and prompt
+fn confirm_count(count: usize, summary: &str, max_downloads: Option<usize>, yes: bool, multi: &MultiProgress) {
+    if let Some(max) = max_downloads.filter(|max| count > *max) {
+        match yes {
+            true => warn!("{summary} is over --max-downloads {max}, continuing because of --yes"),
+            false => exit_with_error_msg(&format!("{summary} is over --max-downloads {max}, pass --yes to download them anyway"), Some(EXIT_ERROR))
+        }
+    }
+    if yes || count <= CONFIRM_THRESHOLD || !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return;
+    }
+    let confirmed = multi.suspend(|| Confirm::new()
+        .with_prompt(format!("Download {summary}?"))
+        .default(false)
+        .interact());
+    match confirmed {
+        Ok(true) => {}
+        Ok(false) => exit_with_error_msg("Download cancelled", Some(EXIT_ERROR)),
+        Err(err) => exit_with_error_msg(&format!("Failed to ask for confirmation: {err}"), Some(EXIT_ERROR))
+    }
+}
+
+// Downloads wait on the writer once this many events are queued
+const PROGRESS_EVENT_BUFFER: usize = 256;
+
+// Where --progress-fd events go. Any descriptor other than stdout and stderr has to be opened
+// by whatever started twdl, e.g. `3>progress.jsonl`.
+fn progress_output(fd: i32) -> Box<dyn Write + Send> {
+    match fd {
+        1 => Box::new(io::stdout()),
+        2 => Box::new(io::stderr()),
+        #[cfg(unix)]
+        fd if fd > 2 => {
+            use std::os::fd::FromRawFd;
+            // Safety: the descriptor was handed to us to write to and nothing else here uses it
+            Box::new(unsafe { std::fs::File::from_raw_fd(fd) })
+        }
+        _ => exit_with_error_msg(&format!("Can't write progress events to file descriptor {fd}"), Some(EXIT_ERROR))
+    }
+}
+
+async fn load_client_pool(path: &str, http_options: &HttpOptions) -> ClientPool {
+    let contents = match read(path).await {
+        Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+        Err(err) => exit_with_error_msg(&format!("Failed to read proxy list: {err}"), Some(EXIT_ERROR))
+    };
+    let proxies: Vec<String> = contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    if proxies.is_empty() {
+        exit_with_error_msg(&format!("No proxies found in {path}"), Some(EXIT_ERROR));
+    }
+    match ClientPool::with_proxies(&proxies, http_options) {
+        Ok(pool) => {
+            info!("Downloading through {} proxies", pool.len());
+            pool
+        }
+        Err(err) => exit_with_error_msg(&format!("Failed to set up HTTP client: {err}"), Some(EXIT_ERROR))
+    }
+}
+
+// One entry per broadcaster or game, on stdout while logs and progress stay on stderr
+fn print_json_summary(summaries: &[RunSummary]) {
+    match serde_json::to_string_pretty(summaries) {
+        Ok(json) => println!("{json}"),
+        Err(err) => error!("Failed to serialize the run summary: {err}")
+    }
+}
+
+// Failing to notify doesn't fail the run, the downloads already finished
+async fn post_webhook(client: &reqwest::Client, url: &str, headers: &[(String, String)], payload: &RunSummary<'_>) {
+    if let Err(err) = notify(client, url, headers, payload).await {
+        warn!("Failed to post to the webhook: {err}");
+    }
+}
+
+// GIFs are only a preview, so a missing ffmpeg skips them instead of stopping the run
+async fn gif_options(gif: bool, duration: f64, width: u32, fps: u32) -> Option<GifOptions> {
+    if !gif {
+        return None;
+    }
+    match ffmpeg_available().await {
+        true => Some(GifOptions { duration, width, fps }),
+        false => {
+            warn!("ffmpeg wasn't found on PATH, skipping --gif");
+            None
+        }
+    }
+}
+
+fn report_gifs<'a>(reports: impl IntoIterator<Item = &'a DownloadReport>) {
+    let ids: Vec<&str> = reports.into_iter()
+        .flat_map(|report| &report.downloaded)
+        .filter(|clip| clip.gif.is_some())
+        .map(|clip| clip.clip_id.as_str())
+        .collect();
+    if !ids.is_empty() {
+        info!("Made GIFs for {} clips: {}", ids.len(), ids.join(", "));
+    }
+}
+
+async fn handle_search_subcommand(args: SearchCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions, multi: Arc<MultiProgress>) {
+    let pattern = match args.regex {
+        true => args.keyword.clone(),
+        false => regex::escape(&args.keyword)
+    };
+    let matcher = match RegexBuilder::new(&pattern).case_insensitive(!args.case_sensitive).build() {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with_error_msg(&format!("Invalid keyword regex: {err}"), Some(EXIT_ERROR))
+    };
+
+    let session = authenticate(client, args.credentials, AuthMode::App).await;
+    let id = login_or_id(&args.broadcaster_id, &args.broadcaster_login, &session).await;
+    let user = match twdl::twitch_utils::get_user(&id, &session).await {
+        Ok(Some(user)) => user,
+        _ => exit_with_error_msg("Failed to get user info", Some(EXIT_ERROR))
+    };
+    let output_path = match PathBuf::from_str(args.output.as_deref().unwrap_or(".")) {
+        Ok(path) => path,
+        Err(_) => exit_with_error_msg("Invalid path", Some(EXIT_ERROR))
+    };
+
+    let (start, end) = interpret_datetimes(args.start_timestamp, args.end_timestamp, twdl::twitch_utils::convert_ts(&user.created_at));
+    let chunking_type = twdl::twitch_utils::DateChunkingType::ByDuration(TimeDelta::weeks(4));
+    let clips = twdl::twitch_utils::get_clips_chunked(&ClipSource::Broadcaster(id), &session, start, end, chunking_type, Some(100), Duration::ZERO).await;
+    let scanned = clips.len();
+    let matches: Vec<Clip> = clips.into_iter().filter(|clip| matcher.is_match(&clip.title)).collect();
+    // Helix can't search titles, so every clip in the range had to be fetched to find these
+    info!("Scanned {scanned} clips, {} matched \"{}\"", matches.len(), args.keyword);
+
+    if args.list {
+        for clip in &matches {
+            println!("{}\t{}", clip.url, clip.title);
+        }
+        return;
+    }
+    let options = DownloadOptions {
+        concurrency: args.chunk_size.unwrap_or(10),
+        metadata: args.metadata,
+        ..options.clone()
+    };
+    let report = download_clips(Some(multi), client, matches, &output_path, gql, &options, None).await;
+    info!("Downloaded {} clips, {} unavailable, {} failed ({})", report.downloaded.len(), report.unavailable.len(), report.failed.len(), report.throughput());
+}
+
+async fn concat_clips(output: &str, files: &[PathBuf], concat_only: bool) {
+    if files.is_empty() {
+        warn!("No clips were downloaded, skipping --concat");
+        return;
+    }
+    info!("Joining {} clips into {output}, this re-encodes them and can take a while", files.len());
+    if let Err(err) = concat(files, Path::new(output)).await {
+        error!("Failed to join clips: {err}");
+        return;
+    }
+    info!("Wrote {output}");
+    if concat_only {
+        for file in files {
+            if let Err(err) = tokio::fs::remove_file(file).await {
+                warn!("Failed to delete {} ({err})", file.display());
+            }
+        }
+    }
+}
+
+fn exec_callback(hook: &Arc<ExecHook>) -> ClipCallback {
+    let hook = hook.clone();
+    clip_callback(move |clip, path| {
+        let hook = hook.clone();
+        Box::pin(async move { hook.run(clip, path).await })
+    })
+}
+
+// Moves the --exec failures of the clips just downloaded into their report
+fn collect_exec_failures(report: &mut DownloadReport, hook: Option<&ExecHook>) {
+    if let Some(hook) = hook {
+        report.exec_failures = hook.take_failures();
+    }
+}
+
+fn report_exec_failures<'a>(reports: impl IntoIterator<Item = &'a DownloadReport>) {
+    let ids: Vec<&str> = reports.into_iter()
+        .flat_map(|report| &report.exec_failures)
+        .map(|failure| failure.clip_id.as_str())
+        .collect();
+    if !ids.is_empty() {
+        warn!("--exec failed for {} clips: {}", ids.len(), ids.join(", "));
+    }
+}
+
+// Resolves the best source file for every clip, printing the URLs and/or writing them to a playlist.
+// At most `options.concurrency` clips are resolved at once.
+// `multi` draws how many clips were resolved so far, a bar is only added when stderr is a terminal
+async fn output_source_links(clips: &[Clip], client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions, multi: &MultiProgress, link: bool, m3u: Option<&str>) {
+    let bar = io::stderr().is_terminal().then(|| {
+        let bar = multi.add(ProgressBar::new(clips.len() as u64));
+        bar.set_style(ProgressStyle::with_template("Resolving clips {wide_bar} {pos}/{len}").unwrap());
+        bar
+    });
+    // Unordered so one slow lookup doesn't hold up the rest, the index puts the output back in clip order
+    let mut indexed: Vec<_> = stream::iter(clips.iter().enumerate())
+        .map(|(i, clip)| {
+            let bar = bar.as_ref();
+            async move {
+                let result = get_video_source_files(client, clip.id.as_str(), gql).await;
+                if let Some(bar) = bar {
+                    bar.inc(1);
+                }
+                (i, result)
+            }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    indexed.sort_by_key(|(i, _)| *i);
+    let source_file_results: Vec<_> = indexed.into_iter().map(|(_, result)| result).collect();
+    let mut entries = Vec::new();
+    for (clip, result) in clips.iter().zip(&source_file_results) {
+        let files = match result {
+            Ok(files) => files,
+            Err(err) => {
+                error!("Error fetching source URL for clip {}: {err}", clip.id);
+                continue;
+            }
+        };
+        let best = match twdl::select_source_file(files, options.quality) {
+            Some(best) => best,
+            None => {
+                error!("Could not find any source files for clip {}", clip.id);
+                continue;
+            }
+        };
+        if link {
+            println!("{}", best.url.as_str());
+        }
+        entries.push(PlaylistEntry::from_clip(clip, best));
     }
+    if let Some(m3u) = m3u {
+        write_playlist(m3u, &entries).await;
+    }
+}
+
+async fn handle_login_subcommand(args: LoginCommandArgs, client: &reqwest::Client) {
+    let client_id = match args.client_id {
+        Some(id) => id,
+        None => {
+            print!("Client ID: ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).is_err() {
+                exit_with_error_msg("Failed to read client ID", Some(EXIT_ERROR));
+            }
+            line.trim().to_string()
+        }
+    };
+    let client_secret = match rpassword::prompt_password("Client secret: ") {
+        Ok(secret) => secret.trim().to_string(),
+        Err(err) => exit_with_error_msg(&format!("Failed to read client secret: {err}"), Some(EXIT_ERROR))
+    };
+
+    // Make sure the pair actually works before saving it
+    if let Err(err) = TwitchSession::new(client.clone(), &client_id, &client_secret).await {
+        exit_with_error_msg(&format!("Twitch rejected those credentials: {err}"), Some(EXIT_ERROR));
+    }
+
+    let creds = TwitchCredentials { client_id, client_secret };
+    let stored = match serde_json::to_string(&creds) {
+        Ok(stored) => stored,
+        Err(err) => exit_with_error_msg(&format!("Failed to serialize credentials: {err}"), Some(EXIT_ERROR))
+    };
+    match keyring_entry().and_then(|entry| entry.set_password(&stored)) {
+        Ok(_) => info!("Stored credentials in the OS keyring under service \"{KEYRING_SERVICE}\", entry \"{KEYRING_USER}\""),
+        Err(err) => exit_with_error_msg(&format!("Failed to store credentials in keyring: {err}"), Some(EXIT_ERROR))
+    }
+}
+
+async fn handle_verify_subcommand(args: VerifyCommandArgs, client: &reqwest::Client, gql: &GqlConfig, options: &DownloadOptions) {
+    let directory = PathBuf::from(&args.directory);
+    let clips = match &args.manifest {
+        Some(manifest) => read_manifest(Path::new(manifest), &directory).await,
+        None => find_clips(&directory).await
+    };
+    let clips = match clips {
+        Ok(clips) => clips,
+        Err(err) => exit_with_error_msg(&format!("Failed to list clips: {err}"), Some(EXIT_ERROR))
+    };
+
+    let options = DownloadOptions {
+        concurrency: args.chunk_size.unwrap_or(10),
+        ..options.clone()
+    };
+    let results = verify_clips(client, clips, gql, &options, args.probe).await;
+
+    let mut failed = 0;
+    for result in &results {
+        let reason = match &result.status {
+            VerifyStatus::Ok => continue,
+            VerifyStatus::Missing => "file is missing".to_string(),
+            VerifyStatus::SizeMismatch { expected, actual } => format!("expected {expected} bytes, found {actual}"),
+            VerifyStatus::NotVideo => "not an mp4 file".to_string(),
+            VerifyStatus::ChecksumMismatch => "doesn't match its .sha256 checksum".to_string(),
+            VerifyStatus::Error(err) => format!("could not be checked ({err})")
+        };
+        failed += 1;
+        println!("FAIL {}: {reason}", result.path.display());
+    }
+    println!("{} passed, {failed} failed", results.len() - failed);
+    if failed > 0 {
+        process::exit(EXIT_ERROR);
+    }
+}
+
+// Fill in anything not given on the command line from the config file.
+// Precedence is CLI > env > config file > built-in default.
+fn apply_config(config: Config, args: &mut Cli) {
+    if config.concurrency == Some(0) {
+        exit_with_error_msg("concurrency in the config file must be at least 1", None);
+    }
+    args.retries = args.retries.or(config.retries);
+    args.quality = args.quality.or(config.quality);
+
+    let (credentials, output, chunk_size) = match &mut args.command {
+        Commands::Clip(args) => (&mut args.credentials, Some(&mut args.output), None),
+        Commands::Channel(args) => (&mut args.credentials, Some(&mut args.output), Some(&mut args.chunk_size)),
+        Commands::Game(args) => (&mut args.credentials, Some(&mut args.output), Some(&mut args.chunk_size)),
+        Commands::Search(args) => (&mut args.credentials, Some(&mut args.output), Some(&mut args.chunk_size)),
+        Commands::Channels(args) => (&mut args.credentials, Some(&mut args.output), Some(&mut args.chunk_size)),
+        Commands::Info(args) => (&mut args.credentials, None, None),
+        Commands::User(args) => (&mut args.credentials, None, None),
+        Commands::Login(_) | Commands::Verify(_) | Commands::Completions(_) => return
+    };
+    if credentials.is_none() && credentials_from_env().is_none() {
+        *credentials = config.credentials;
+    }
+    *credentials = credentials.take().map(|path| expand_path(&path));
+    if let Some(output) = output {
+        *output = output.take().or(config.output).map(|path| expand_path(&path));
+    }
+    if let Some(chunk_size) = chunk_size {
+        *chunk_size = chunk_size.or(config.concurrency);
+    }
+}
+
+fn writes_many_to_stdout(command: &Commands) -> bool {
+    let output = match command {
+        Commands::Channel(args) => args.output.as_deref(),
+        Commands::Game(args) => args.output.as_deref(),
+        Commands::Search(args) => args.output.as_deref(),
+        Commands::Channels(args) => args.output.as_deref(),
+        _ => None
+    };
+    output == Some("-")
+}
+
+// Expands a leading ~ and $VAR or ${VAR}, which shells leave alone inside quotes and config files never get.
+// Unset variables are kept as written.
+fn expand_path(path: &str) -> String {
+    let path = match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => format!("{}{rest}", home.display()),
+        _ => path.to_string()
+    };
+    let variable = Regex::new(r"\$(?:\{(\w+)\}|(\w+))").expect("Failed to parse regex string");
+    variable.replace_all(&path, |caps: &Captures| {
+        let name = caps.get(1).or(caps.get(2)).map_or("", |name| name.as_str());
+        env::var(name).unwrap_or_else(|_| caps[0].to_string())
+    }).into_owned()
+}
+
+fn json_log_line(ts: &str, record: &log::Record) -> String {
+    let mut line = serde_json::Map::new();
+    line.insert("ts".to_string(), ts.into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("message".to_string(), record.args().to_string().into());
+    if let Some(clip_id) = record.key_values().get(log::kv::Key::from("clip_id")) {
+        line.insert("clip_id".to_string(), clip_id.to_string().into());
+    }
+    serde_json::Value::Object(line).to_string()
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Cli::parse();
+    let mut args = Cli::parse();
+    if let Commands::Completions(completions) = &args.command {
+        clap_complete::generate(completions.shell, &mut Cli::command(), "twdl", &mut io::stdout());
+        return;
+    }
     let multi = Arc::new(MultiProgress::new());
+    let colors = match args.color {
+        ColorMode::Auto => env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        ColorMode::Always => true,
+        ColorMode::Never => false
+    };
+    // Left to console's own terminal detection unless asked for or NO_COLOR says otherwise
+    if args.color != ColorMode::Auto || !colors {
+        console::set_colors_enabled(colors);
+        console::set_colors_enabled_stderr(colors);
+    }
+    if args.color == ColorMode::Never {
+        multi.set_draw_target(ProgressDrawTarget::hidden());
+    }
 
     {
-        // for outputting links, limit logs to errors
-        let link = match &args.command {
-            Commands::Clip(args) => args.link,
-            Commands::Channel(args) => args.link
+        // for outputting links or when asked to be quiet, limit logs to errors
+        let quiet = match &args.command {
+            Commands::Clip(args) => args.link || args.quiet,
+            Commands::Channel(args) => args.link,
+            Commands::Game(args) => args.link,
+            Commands::Search(_) | Commands::Channels(_) => false,
+            Commands::Info(_) | Commands::User(_) | Commands::Login(_) | Commands::Verify(_) | Commands::Completions(_) => false
         };
-        let log_level = match link {
+        let log_level = match quiet {
             true => log::LevelFilter::Error,
             false => log::LevelFilter::Info
         };
+        // -v wins over the quiet modes, but only raises twdl's own logs, not those of its dependencies
+        let twdl_level = match args.verbose {
+            0 => log_level,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace
+        };
 
+        let log_format = args.log_format;
+        // The logger isn't set up yet, so this can't go through exit_with_error_msg
+        let log_file = args.log_file.as_ref().map(|path| {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Mutex::new(file),
+                Err(err) => {
+                    eprintln!("Failed to open log file {path}: {err}");
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        });
+
+        // MultiProgress draws to stderr, so logs never end up in --link or --stdout output
         let multi_for_logs = multi.clone();
         env_logger::Builder::new()
             .format(move |buf, record| {
                 let ts = buf.timestamp();
-                let msg = format!("{} [{}] {}", ts, record.level(), record.args());
-                multi_for_logs.println(msg).unwrap();
+                let msg = match log_format {
+                    LogFormat::Text => format!("{} [{}] {}", ts, record.level(), record.args()),
+                    LogFormat::Json => json_log_line(&ts.to_string(), record)
+                };
+                if let Some(file) = &log_file {
+                    // A poisoned lock only means another log call panicked, the file is still usable
+                    let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    writeln!(file, "{msg}")?;
+                }
+                // A hidden MultiProgress drops printed lines, which it is with --color never or when stderr isn't a terminal
+                match multi_for_logs.is_hidden() {
+                    true => eprintln!("{msg}"),
+                    false => multi_for_logs.println(msg).unwrap()
+                }
                 Ok(())
             })
             .filter_level(log_level)
+            .filter_module("twdl", twdl_level)
             .init();
     }
 
+    let timeout = args.timeout.map(Duration::from_secs);
+    let http_options = HttpOptions {
+        proxy: args.proxy.clone(),
+        user_agent: args.user_agent.clone(),
+        connect_timeout: timeout
+    };
+    let client = match build_http_client(&http_options) {
+        Ok(client) => client,
+        Err(err) => exit_with_error_msg(&format!("Failed to set up HTTP client: {err}"), Some(EXIT_ERROR))
+    };
+    let client_pool = match &args.proxy_list {
+        Some(path) => Some(Arc::new(load_client_pool(path, &http_options).await)),
+        None => None
+    };
+
+    let gql = GqlConfig {
+        client_id: args.gql_client_id.clone()
+            .or_else(|| env::var("TWITCH_GQL_CLIENT_ID").ok())
+            .unwrap_or_else(|| twdl::DEFAULT_GQL_CLIENT_ID.to_string()),
+        endpoint: args.gql_endpoint.clone()
+            .or_else(|| env::var("TWITCH_GQL_ENDPOINT").ok())
+            .unwrap_or_else(|| twdl::DEFAULT_GQL_ENDPOINT.to_string()),
+        cache: Some(Arc::new(match args.cache_sources {
+            true => SourceCache::persistent(DEFAULT_SOURCE_TTL).await,
+            false => SourceCache::in_memory(DEFAULT_SOURCE_TTL)
+        })),
+        resolve_timeout: args.resolve_timeout.map(Duration::from_secs)
+    };
+
+    let (start, end) = match &args.command {
+        Commands::Channel(args) => (args.start_timestamp.as_ref(), args.end_timestamp.as_ref()),
+        Commands::Game(args) => (args.start_timestamp.as_ref(), args.end_timestamp.as_ref()),
+        Commands::Search(args) => (args.start_timestamp.as_ref(), args.end_timestamp.as_ref()),
+        _ => (None, None)
+    };
+    validate_date_range(start, end);
+    let (created_after, created_before) = match &args.command {
+        Commands::Channel(args) => (args.created_after.as_ref(), args.created_before.as_ref()),
+        Commands::Game(args) => (args.created_after.as_ref(), args.created_before.as_ref()),
+        _ => (None, None)
+    };
+    validate_created_range(created_after, created_before);
+
+    // Checked up front so a long run doesn't fail on its first clip
+    let ffmpeg_flag = match &args.command {
+        Commands::Clip(args) => args.audio_only.then_some("--audio-only")
+            .or((args.trim_start.is_some() || args.trim_end.is_some()).then_some("--trim-start/--trim-end")),
+        Commands::Channel(args) => args.embed_metadata.then_some("--embed-metadata")
+            .or(args.audio_only.then_some("--audio-only"))
+            .or(args.concat.as_ref().map(|_| "--concat")),
+        Commands::Game(args) => args.embed_metadata.then_some("--embed-metadata")
+            .or(args.audio_only.then_some("--audio-only"))
+            .or(args.concat.as_ref().map(|_| "--concat")),
+        _ => None
+    };
+    if let Some(flag) = ffmpeg_flag {
+        let available = ffmpeg_available().await;
+        if !available {
+            exit_with_error_msg(&format!("{flag} needs ffmpeg, which wasn't found on PATH"), Some(EXIT_ERROR));
+        }
+    }
+
+    let cleared = match args.refresh_cache {
+        true => user_cache::clear().await,
+        false => Ok(())
+    };
+    if let Err(err) = cleared {
+        exit_with_error_msg(&err.to_string(), Some(EXIT_ERROR));
+    }
+
+    match load_config().await {
+        Ok(config) => apply_config(config, &mut args),
+        Err(err) => exit_with_error_msg(&format!("Failed to load config file: {err}"), Some(EXIT_ERROR))
+    }
+    // After apply_config, so an output of "-" from the config file is caught too
+    if writes_many_to_stdout(&args.command) {
+        exit_with_error_msg("Writing to standard output only works for a single clip, use the clip command", Some(EXIT_ERROR));
+    }
+
+    let (events, progress_writer) = match args.progress_fd {
+        Some(fd) => {
+            let (sender, receiver) = channel(PROGRESS_EVENT_BUFFER);
+            (Some(sender), Some(tokio::spawn(write_json_lines(progress_output(fd), receiver))))
+        }
+        None => (None, None)
+    };
+
+    let defaults = DownloadOptions::default();
+    let download_options = DownloadOptions {
+        timeout,
+        retries: args.retries.unwrap_or(defaults.retries),
+        retry_passes: args.retry_failed_passes.unwrap_or(defaults.retry_passes),
+        split: args.split.unwrap_or(defaults.split),
+        quality: args.quality,
+        min_quality: args.min_quality,
+        rate_limiter: args.max_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+        retry_budget: args.retry_budget.map(|limit| Arc::new(RetryBudget::new(limit))),
+        client_pool,
+        events,
+        ..defaults
+    };
+
     match args.command {
         Commands::Clip(args) => {
-            handle_clip_subcommand(args).await
+            handle_clip_subcommand(args, &client, &gql, &download_options).await
         }
         Commands::Channel(args) => {
-            handle_channel_subcommand(args, multi).await
+            handle_channel_subcommand(args, &client, &gql, &download_options, multi).await
+        }
+        Commands::Info(args) => {
+            handle_info_subcommand(args, &client).await
+        }
+        Commands::User(args) => {
+            handle_user_subcommand(args, &client).await
         }
+        Commands::Game(args) => {
+            handle_game_subcommand(args, &client, &gql, &download_options, multi).await
+        }
+        Commands::Login(args) => {
+            handle_login_subcommand(args, &client).await
+        }
+        Commands::Verify(args) => {
+            handle_verify_subcommand(args, &client, &gql, &download_options).await
+        }
+        Commands::Search(args) => {
+            handle_search_subcommand(args, &client, &gql, &download_options, multi).await
+        }
+        Commands::Channels(args) => {
+            handle_channels_subcommand(args, &client, &gql, &download_options, multi).await
+        }
+        // Printed before anything was set up
+        Commands::Completions(_) => {}
     }
 
-}
\ No newline at end of file
+    if let Some(budget) = &download_options.retry_budget {
+        info!("Used {} of {} retries in the retry budget", budget.used(), budget.limit());
+    }
+
+    // The writer stops once the last sender is gone, waited on so no trailing events are lost
+    drop(download_options);
+    if let Some(writer) = progress_writer {
+        let _ = writer.await;
+    }
+
+    let saved = match &gql.cache {
+        Some(cache) => cache.save().await,
+        None => Ok(())
+    };
+    if let Err(err) = saved {
+        error!("{err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date_range_must_move_forward() {
+        assert!(is_ordered("2024-01-01T00:00:00Z", "2024-02-01T00:00:00Z"));
+        assert!(!is_ordered("2024-01-01T00:00:00Z", "2024-01-01T00:00:00Z"));
+        assert!(!is_ordered("2024-02-01T00:00:00Z", "2024-01-01T00:00:00Z"));
+        // The same instant written two ways is still empty
+        assert!(!is_ordered("1704067200", "2024-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn parses_epoch_seconds_and_millis() {
+        let expected = DateTime::from_timestamp(1_700_000_000, 0);
+        assert_eq!(parse_epoch("1700000000"), expected);
+        assert_eq!(parse_epoch("1700000000000"), expected);
+        assert_eq!(parse_epoch("1700000000123"), DateTime::from_timestamp_millis(1_700_000_000_123));
+    }
+
+    #[test]
+    fn rejects_non_epoch_input() {
+        for input in ["", "-1700000000", "1700000000.5", "2024-01-01", "17000abc00", "20240101", "2024", "17000000000"] {
+            assert_eq!(parse_epoch(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn since_window_ends_now() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let (start, end) = since_window(TimeDelta::days(7), now);
+        assert_eq!(end, now);
+        assert_eq!(start, DateTime::from_timestamp(1_700_000_000 - 7 * 86_400, 0).unwrap());
+    }
+
+    #[test]
+    fn trim_end_past_the_clip_is_clamped() {
+        assert_eq!(validate_trim(Some(2.0), Some(45.0), Some(30.0)), None);
+        assert_eq!(validate_trim(Some(2.0), Some(20.0), Some(30.0)), Some(20.0));
+        assert_eq!(validate_trim(None, Some(45.0), None), Some(45.0));
+    }
+
+    #[test]
+    fn stdout_output_from_config_is_rejected() {
+        let mut args = Cli::try_parse_from(["twdl", "channel", "-l", "somestreamer"]).unwrap();
+        apply_config(Config { output: Some("-".to_string()), ..Config::default() }, &mut args);
+        assert!(writes_many_to_stdout(&args.command));
+
+        let mut args = Cli::try_parse_from(["twdl", "channel", "-l", "somestreamer", "-o", "clips"]).unwrap();
+        apply_config(Config { output: Some("-".to_string()), ..Config::default() }, &mut args);
+        assert!(!writes_many_to_stdout(&args.command));
+    }
+
+    #[test]
+    fn expands_home_and_variables() {
+        let home = dirs::home_dir().unwrap().display().to_string();
+        assert_eq!(expand_path("~"), home);
+        assert_eq!(expand_path("~/sub"), format!("{home}/sub"));
+        let var = env::var("HOME").unwrap();
+        assert_eq!(expand_path("$HOME/x"), format!("{var}/x"));
+        assert_eq!(expand_path("${HOME}/x"), format!("{var}/x"));
+    }
+
+    #[test]
+    fn leaves_paths_without_anything_to_expand() {
+        assert_eq!(expand_path("clips/2024"), "clips/2024");
+        assert_eq!(expand_path("~user/clips"), "~user/clips");
+        assert_eq!(expand_path("$TWDL_UNSET_TEST_VARIABLE/clips"), "$TWDL_UNSET_TEST_VARIABLE/clips");
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::HashSet, path::{Path, PathBuf}};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::error::{Result, TwdlError};
+
+// `manifest.json` in an output directory, recording what has been downloaded there.
+// It's a plain json array of objects with an `id`, so `twdl verify --manifest` can read it too.
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub id: String,
+
+    pub path: PathBuf,
+
+    pub size: u64,
+
+    // RFC 3339, when the clip was downloaded
+    pub downloaded_at: String,
+
+    // RFC 3339, when the clip was created on twitch. Missing from manifests written before it was tracked
+    #[serde(default)]
+    pub created_at: Option<String>
+}
+
+impl ManifestEntry {
+    pub fn new(id: String, path: PathBuf, size: u64, created_at: String) -> Self {
+        ManifestEntry { id, path, size, downloaded_at: Utc::now().to_rfc3339(), created_at: Some(created_at) }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>
+}
+
+impl Manifest {
+    // A missing or unreadable manifest is treated as empty, everything is downloaded again
+    pub async fn load(directory: &Path) -> Self {
+        let path = directory.join(MANIFEST_FILE);
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(_) => return Manifest::default()
+        };
+        match serde_json::from_slice(&contents) {
+            Ok(entries) => Manifest { entries },
+            Err(err) => {
+                warn!("Ignoring corrupt manifest {} ({err})", path.display());
+                Manifest::default()
+            }
+        }
+    }
+
+    pub async fn save(&self, directory: &Path) -> Result<()> {
+        let path = directory.join(MANIFEST_FILE);
+        let contents = serde_json::to_string_pretty(&self.entries)?;
+        fs::write(&path, contents).await
+            .map_err(|err| TwdlError::io(format!("Failed to write manifest {}", path.display()), err))
+    }
+
+    pub fn ids(&self) -> HashSet<&str> {
+        self.entries.iter().map(|entry| entry.id.as_str()).collect()
+    }
+
+    // Creation time of the newest clip recorded, if any entry has one
+    pub fn latest_created_at(&self) -> Option<DateTime<Utc>> {
+        self.entries.iter()
+            .filter_map(|entry| entry.created_at.as_deref())
+            .filter_map(|created_at| DateTime::parse_from_rfc3339(created_at).ok())
+            .map(|created_at| created_at.with_timezone(&Utc))
+            .max()
+    }
+
+    // Replaces any older entry for the same clip
+    pub fn record(&mut self, entry: ManifestEntry) {
+        self.entries.retain(|existing| existing.id != entry.id);
+        self.entries.push(entry);
+    }
+}
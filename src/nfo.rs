@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use log::error;
+use tokio::fs;
+use twitch_api::helix::clips::Clip;
+
+// Kodi/Jellyfin style `.nfo` sidecars, so media servers show clip details without scraping
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn build_nfo(clip: &Clip) -> String {
+    // created_at is RFC 3339, premiered only wants the day
+    let premiered = clip.created_at.as_str().get(..10).unwrap_or(clip.created_at.as_str());
+    // Runtime is in whole minutes, round up so short clips don't show as 0
+    let runtime = (clip.duration / 60.0).ceil() as u64;
+    let plot = format!("Clipped by {} from {}'s stream", clip.creator_name, clip.broadcaster_name);
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <movie>\n\
+         \x20 <title>{}</title>\n\
+         \x20 <plot>{}</plot>\n\
+         \x20 <premiered>{premiered}</premiered>\n\
+         \x20 <runtime>{runtime}</runtime>\n\
+         \x20 <studio>{}</studio>\n\
+         \x20 <actor>\n\
+         \x20   <name>{}</name>\n\
+         \x20   <role>Clipper</role>\n\
+         \x20 </actor>\n\
+         \x20 <uniqueid type=\"twitch\" default=\"true\">{}</uniqueid>\n\
+         </movie>\n",
+        escape(&clip.title),
+        escape(&plot),
+        escape(clip.broadcaster_name.as_str()),
+        escape(clip.creator_name.as_str()),
+        escape(clip.id.as_str())
+    )
+}
+
+pub async fn save_nfo(clip: &Clip, directory: &Path) {
+    let output_path = directory.join(format!("{}.nfo", &clip.id));
+    if let Err(err) = fs::write(output_path, build_nfo(clip)).await {
+        error!(clip_id = clip.id.as_str(); "Failed to write nfo file: {err}");
+    }
+}
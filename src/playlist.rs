@@ -0,0 +1,38 @@
+use std::path::Path;
+
+use tokio::fs;
+use twitch_api::helix::clips::Clip;
+
+use crate::{error::{Result, TwdlError}, SourceFile};
+
+// Extended M3U playlists pointing at resolved clip source URLs
+
+pub struct PlaylistEntry {
+    pub title: String,
+
+    // Length in seconds, -1 when unknown
+    pub duration: i64,
+
+    pub url: String
+}
+
+impl PlaylistEntry {
+    pub fn from_clip(clip: &Clip, source: &SourceFile) -> Self {
+        PlaylistEntry {
+            title: clip.title.clone(),
+            duration: clip.duration.round() as i64,
+            url: source.url.to_string()
+        }
+    }
+}
+
+pub async fn write_m3u(path: &Path, entries: &[PlaylistEntry]) -> Result<()> {
+    let mut contents = String::from("#EXTM3U\n");
+    for entry in entries {
+        // EXTINF titles must stay on a single line
+        let title = entry.title.replace(['\r', '\n'], " ");
+        contents.push_str(&format!("#EXTINF:{},{}\n{}\n", entry.duration, title, entry.url));
+    }
+    fs::write(path, contents).await
+        .map_err(|err| TwdlError::io(format!("Failed to write playlist {}", path.display()), err))
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use futures_util::{stream, StreamExt};
+use log::{info, warn};
+use twitch_api::helix::clips::Clip;
+
+use crate::{check_min_quality, get_video_source_files, head_content_length, select_source_file, DownloadOptions, GqlConfig, SourceFile};
+
+// Resolves every clip's source URL and its size up front, so the total download size is known.
+// Costs an extra request per clip, the downloads reuse the URLs found here instead of resolving them again.
+
+#[derive(Clone, Debug)]
+pub struct PrescanEntry {
+    pub source: SourceFile,
+
+    // None when the server didn't send a Content-Length
+    pub size: Option<u64>
+}
+
+#[derive(Default, Debug)]
+pub struct Prescan {
+    // Keyed by clip id, clips that couldn't be resolved are left out and resolved again when downloaded
+    pub entries: HashMap<String, PrescanEntry>
+}
+
+impl Prescan {
+    // None if any clip's size is unknown
+    pub fn total_size(&self) -> Option<u64> {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+}
+
+pub async fn prescan_clips(client: &reqwest::Client, clips: &[Clip], gql: &GqlConfig, options: &DownloadOptions) -> Prescan {
+    let entries: Vec<_> = stream::iter(clips)
+        .map(|clip| async move {
+            let files = get_video_source_files(client, clip.id.as_str(), gql).await.ok()?;
+            // Left out so the download reports why
+            check_min_quality(&files, options.min_quality, clip.id.as_str()).ok()?;
+            let source = select_source_file(&files, options.quality)?.clone();
+            let size = match head_content_length(client, source.url.clone()).await {
+                Ok(size) => size,
+                Err(err) => {
+                    warn!(clip_id = clip.id.as_str(); "Failed to get the size of clip {} ({err})", clip.id);
+                    None
+                }
+            };
+            Some((clip.id.to_string(), PrescanEntry { source, size }))
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    let prescan = Prescan { entries: entries.into_iter().flatten().collect() };
+    match prescan.total_size() {
+        Some(total) => info!("Prescanned {} clips, {total} bytes in total", prescan.entries.len()),
+        None => info!("Prescanned {} clips, some sizes are unknown", prescan.entries.len())
+    }
+    prescan
+}
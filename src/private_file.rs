@@ -0,0 +1,46 @@
+use std::{io, path::Path};
+
+use tokio::fs;
+
+// Writes files holding secrets, like cached tokens or signed source URLs, readable by the owner only
+
+#[cfg(unix)]
+pub(crate) async fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .await?;
+    // `mode` only applies on creation, tighten files left by older runs too
+    fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    file.write_all(contents.as_bytes()).await
+}
+
+#[cfg(not(unix))]
+pub(crate) async fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents).await
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn only_the_owner_can_read() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("secret.json");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, "new").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
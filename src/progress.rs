@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressFinish, ProgressStyle};
+use twitch_api::helix::clips::Clip;
+
+// Aggregate byte progress across every download of a `download_clips` run
+
+// Files add their size to the bar as they start, unless it was sized up front from a prescan
+#[derive(Clone, Debug)]
+pub struct ByteBar {
+    bar: ProgressBar,
+
+    presized: bool
+}
+
+impl ByteBar {
+    pub fn new(bar: ProgressBar) -> Self {
+        ByteBar { bar, presized: false }
+    }
+
+    // `total` is None when some file's size is unknown
+    pub fn presized(bar: ProgressBar, total: Option<u64>) -> Self {
+        match total {
+            Some(total) => bar.set_length(total),
+            None => switch_to_unknown_length(&bar)
+        }
+        ByteBar { bar, presized: true }
+    }
+}
+
+pub fn byte_progress_bar(multi: &MultiProgress) -> ProgressBar {
+    let bar = multi.add(ProgressBar::new(0));
+    bar.set_style(ProgressStyle::with_template("{wide_bar} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec})").unwrap());
+    bar
+}
+
+// Longer titles are cut short so each clip's line fits next to the spinner
+const CLIP_LABEL_LENGTH: usize = 40;
+
+// A spinner naming a clip while it downloads, removed again when dropped
+pub(crate) fn clip_bar(multi: &MultiProgress, clip: &Clip) -> ProgressBar {
+    let bar = multi.add(ProgressBar::new_spinner().with_finish(ProgressFinish::AndClear));
+    bar.set_style(ProgressStyle::with_template("{spinner} {msg}").unwrap());
+    let title = clip.title.trim();
+    let label = match title.chars().count() > CLIP_LABEL_LENGTH {
+        true => format!("{}...", title.chars().take(CLIP_LABEL_LENGTH).collect::<String>()),
+        false if title.is_empty() => clip.id.to_string(),
+        false => title.to_string()
+    };
+    bar.set_message(format!("{label} ({:.0}s)", clip.duration));
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+// Once any clip's size is unknown the total can't be shown, only what has been downloaded so far
+fn switch_to_unknown_length(bar: &ProgressBar) {
+    bar.unset_length();
+    bar.set_style(ProgressStyle::with_template("{spinner} {binary_bytes} downloaded ({binary_bytes_per_sec})").unwrap());
+}
+
+// One file's share of the aggregate bar, taken back out if the attempt doesn't finish
+pub(crate) struct ByteProgress<'a> {
+    bar: Option<&'a ByteBar>,
+
+    length: u64,
+
+    position: u64,
+
+    finished: bool
+}
+
+impl<'a> ByteProgress<'a> {
+    pub(crate) fn new(bar: Option<&'a ByteBar>) -> Self {
+        ByteProgress { bar, length: 0, position: 0, finished: false }
+    }
+
+    pub(crate) fn start(&mut self, total_bytes: Option<u64>) {
+        let Some(bar) = self.bar.filter(|bar| !bar.presized) else {
+            return;
+        };
+        match total_bytes {
+            Some(total) => {
+                bar.bar.inc_length(total);
+                self.length = total;
+            }
+            None => switch_to_unknown_length(&bar.bar)
+        }
+    }
+
+    pub(crate) fn advance(&mut self, bytes: u64) {
+        if let Some(bar) = self.bar {
+            bar.bar.inc(bytes);
+            self.position += bytes;
+        }
+    }
+
+    pub(crate) fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for ByteProgress<'_> {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.filter(|_| !self.finished) {
+            bar.bar.dec_length(self.length);
+            bar.bar.set_position(bar.bar.position().saturating_sub(self.position));
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use tokio::{sync::Mutex, time::{sleep, Instant}};
+
+// Token bucket shared by every concurrent download to cap their combined throughput
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+
+    bucket: Mutex<Bucket>
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // Goes negative when a chunk is bigger than what's available, later callers wait off the debt
+    available: f64,
+
+    last_refill: Instant
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec as f64;
+        RateLimiter {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket { available: bytes_per_sec, last_refill: Instant::now() })
+        }
+    }
+
+    // Waits until `bytes` more can be written without going over the rate
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.bytes_per_sec;
+            // Cap at one second's worth so idle time can't be saved up into a burst
+            bucket.available = (bucket.available + refill).min(self.bytes_per_sec);
+            bucket.last_refill = now;
+            bucket.available -= bytes as f64;
+
+            if bucket.available < 0.0 {
+                Duration::from_secs_f64(-bucket.available / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join_all;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn spreads_requests_over_the_rate() {
+        let limiter = RateLimiter::new(1000);
+        let started = Instant::now();
+        // A second's worth each, the first is covered by the full bucket
+        let finished = join_all((0..5).map(|_| async {
+            limiter.acquire(1000).await;
+            started.elapsed().as_secs_f64().round() as u64
+        })).await;
+        assert_eq!(finished, [0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_time_is_not_saved_up() {
+        let limiter = RateLimiter::new(1000);
+        sleep(Duration::from_secs(10)).await;
+        let started = Instant::now();
+        limiter.acquire(1000).await;
+        limiter.acquire(1000).await;
+        assert_eq!(started.elapsed().as_secs_f64().round(), 1.0);
+    }
+}
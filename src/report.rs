@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use indicatif::HumanBytes;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::error::{Result, TwdlError};
+
+// Outcome of a `download_clips` run
+
+#[derive(Debug, Default, Serialize)]
+pub struct DownloadReport {
+    // In the order the clips were given, not the order they finished in
+    pub downloaded: Vec<DownloadedClip>,
+
+    // Already in the manifest when running with `sync`
+    pub skipped: usize,
+
+    // Ids of clips twitch listed no qualities for or returned no clip for, usually deleted or still processing
+    pub unavailable: Vec<String>,
+
+    // Clips whose best quality was below `DownloadOptions::min_quality`
+    pub low_quality: Vec<SkippedClip>,
+
+    // Clips that still failed after every retry pass
+    pub failed: Vec<FailedClip>,
+
+    // Downloaded clips whose --exec command failed, filled in by the CLI since the library only runs callbacks
+    pub exec_failures: Vec<ExecFailure>,
+
+    // Wall time spent downloading, including retry passes and post-processing
+    pub download_seconds: f64,
+
+    pub average_bytes_per_second: u64,
+
+    // Fastest batch of `concurrency` clips, measured from when the batch started to when its last clip finished
+    pub peak_bytes_per_second: u64
+}
+
+impl DownloadReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.downloaded.iter().map(|clip| clip.size).sum()
+    }
+
+    // e.g. "1.20 GiB in 42.0s, 29.26 MiB/s average, 41.02 MiB/s peak"
+    pub fn throughput(&self) -> String {
+        format!(
+            "{} in {:.1}s, {}/s average, {}/s peak",
+            HumanBytes(self.total_bytes()),
+            self.download_seconds,
+            HumanBytes(self.average_bytes_per_second),
+            HumanBytes(self.peak_bytes_per_second)
+        )
+    }
+}
+
+pub(crate) fn bytes_per_second(bytes: u64, seconds: f64) -> u64 {
+    match seconds > 0.0 {
+        true => (bytes as f64 / seconds) as u64,
+        false => 0
+    }
+}
+
+// A report with the run's details, as posted by --webhook and printed by --json.
+// The report's own fields sit at the top level next to these.
+#[derive(Serialize)]
+pub struct RunSummary<'a> {
+    // Broadcaster login or game id the clips were fetched for
+    pub source: &'a str,
+
+    pub started_at: String,
+
+    pub finished_at: String,
+
+    pub elapsed_seconds: i64,
+
+    pub total_bytes: u64,
+
+    pub counts: RunCounts,
+
+    // Why nothing was downloaded, e.g. a broadcaster in a channels batch that couldn't be looked up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(flatten)]
+    pub report: &'a DownloadReport
+}
+
+#[derive(Serialize)]
+pub struct RunCounts {
+    pub downloaded: usize,
+
+    pub skipped: usize,
+
+    pub unavailable: usize,
+
+    pub low_quality: usize,
+
+    pub failed: usize,
+
+    pub exec_failed: usize
+}
+
+impl<'a> RunSummary<'a> {
+    pub fn new(source: &'a str, started_at: DateTime<Utc>, report: &'a DownloadReport) -> Self {
+        let finished_at = Utc::now();
+        RunSummary {
+            source,
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            elapsed_seconds: (finished_at - started_at).num_seconds(),
+            total_bytes: report.total_bytes(),
+            counts: RunCounts {
+                downloaded: report.downloaded.len(),
+                skipped: report.skipped,
+                unavailable: report.unavailable.len(),
+                low_quality: report.low_quality.len(),
+                failed: report.failed.len(),
+                exec_failed: report.exec_failures.len()
+            },
+            error: None,
+            report
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadedClip {
+    pub clip_id: String,
+
+    pub path: PathBuf,
+
+    // Bytes on disk after post-processing
+    pub size: u64,
+
+    // Preview written with `DownloadOptions::gif`
+    pub gif: Option<PathBuf>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedClip {
+    pub clip_id: String,
+
+    pub reason: String
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedClip {
+    pub clip_id: String,
+
+    pub url: String,
+
+    pub error: String
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecFailure {
+    pub clip_id: String,
+
+    // None when the command couldn't be started or was killed by a signal
+    pub exit_code: Option<i32>,
+
+    pub error: String
+}
+
+// A json array when `path` ends in .json, otherwise one `url<TAB>error` line per clip
+pub async fn write_failures(path: &Path, failed: &[FailedClip]) -> Result<()> {
+    let contents = match path.extension().is_some_and(|ext| ext == "json") {
+        true => serde_json::to_string_pretty(failed)?,
+        false => failed.iter()
+            .map(|clip| format!("{}\t{}\n", clip.url, clip.error.replace(['\r', '\n', '\t'], " ")))
+            .collect()
+    };
+    fs::write(path, contents).await
+        .map_err(|err| TwdlError::io(format!("Failed to write failures log {}", path.display()), err))
+}
@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Retries shared by every download of a run, so an outage fails the remaining clips quickly
+// instead of retrying each of them in turn
+
+#[derive(Debug)]
+pub struct RetryBudget {
+    limit: u64,
+
+    used: AtomicU64
+}
+
+impl RetryBudget {
+    pub fn new(limit: u64) -> Self {
+        RetryBudget { limit, used: AtomicU64::new(0) }
+    }
+
+    // Takes one retry, false once they've all been used
+    pub fn try_take(&self) -> bool {
+        self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| (used < self.limit).then_some(used + 1)).is_ok()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.used() >= self.limit
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
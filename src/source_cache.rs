@@ -0,0 +1,112 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{error::{Result, TwdlError}, private_file::write_private, SourceFile, SourceInfo};
+
+// Resolved source files by clip id, so a clip isn't resolved twice in a run, or across runs when persisted.
+// Source URLs carry a playback token that expires, entries older than the TTL are resolved again.
+
+pub const DEFAULT_SOURCE_TTL: TimeDelta = TimeDelta::minutes(15);
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct CachedFile {
+    quality: u32,
+
+    frame_rate: u32,
+
+    url: String
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct CachedSources {
+    // Unix timestamp in seconds
+    fetched_at: i64,
+
+    // Absent in caches written before durations were kept
+    #[serde(default)]
+    duration_ms: Option<u64>,
+
+    files: Vec<CachedFile>
+}
+
+#[derive(Debug)]
+pub struct SourceCache {
+    entries: Mutex<HashMap<String, CachedSources>>,
+
+    ttl: TimeDelta,
+
+    // Where the cache is persisted, in memory only when None
+    path: Option<PathBuf>
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("twdl").join("sources.json"))
+}
+
+impl SourceCache {
+    pub fn in_memory(ttl: TimeDelta) -> Self {
+        SourceCache { entries: Mutex::new(HashMap::new()), ttl, path: None }
+    }
+
+    // Starts from what's in the OS cache dir, an unreadable cache file is ignored
+    pub async fn persistent(ttl: TimeDelta) -> Self {
+        let path = cache_path();
+        let entries = match &path {
+            Some(path) => fs::read(path).await.ok()
+                .and_then(|contents| serde_json::from_slice(&contents).ok())
+                .unwrap_or_default(),
+            None => HashMap::new()
+        };
+        SourceCache { entries: Mutex::new(entries), ttl, path }
+    }
+
+    pub fn get(&self, clip_id: &str) -> Option<SourceInfo> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.get(clip_id)?;
+        let age = Utc::now() - DateTime::from_timestamp(cached.fetched_at, 0)?;
+        if age >= self.ttl {
+            return None;
+        }
+        let files = cached.files.iter()
+            .map(|file| Some(SourceFile { quality: file.quality, frame_rate: file.frame_rate, url: Url::parse(&file.url).ok()? }))
+            .collect::<Option<Vec<SourceFile>>>()?;
+        Some(SourceInfo { duration_ms: cached.duration_ms, files })
+    }
+
+    pub fn insert(&self, clip_id: &str, info: &SourceInfo) {
+        let files = info.files.iter()
+            .map(|file| CachedFile { quality: file.quality, frame_rate: file.frame_rate, url: file.url.to_string() })
+            .collect();
+        let cached = CachedSources { fetched_at: Utc::now().timestamp(), duration_ms: info.duration_ms, files };
+        self.entries.lock().unwrap().insert(clip_id.to_string(), cached);
+    }
+
+    // For sources that turned out to be expired before the ttl ran out
+    pub fn remove(&self, clip_id: &str) {
+        self.entries.lock().unwrap().remove(clip_id);
+    }
+
+    // Writes the cache back to disk, dropping stale entries. Does nothing for in-memory caches
+    pub async fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let contents = {
+            let mut entries = self.entries.lock().unwrap();
+            let cutoff = (Utc::now() - self.ttl).timestamp();
+            entries.retain(|_, cached| cached.fetched_at > cutoff);
+            serde_json::to_string(&*entries)?
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await
+                .map_err(|err| TwdlError::io(format!("Failed to create {}", parent.display()), err))?;
+        }
+        // Source URLs carry a signed playback token, so the file is kept as private as the token cache
+        write_private(path, &contents).await
+            .map_err(|err| TwdlError::io(format!("Failed to write source cache {}", path.display()), err))
+    }
+}
@@ -0,0 +1,134 @@
+use std::{io::SeekFrom, path::Path, sync::{atomic::{AtomicU64, Ordering}, Mutex}};
+
+use futures_util::{future::try_join_all, StreamExt};
+use reqwest::{header::{ACCEPT_RANGES, CONTENT_LENGTH, RANGE}, StatusCode, Url};
+use tokio::{fs::{File, OpenOptions}, io::{AsyncSeekExt, AsyncWriteExt}, time::timeout};
+
+use crate::{
+    emit,
+    error::{Result, TwdlError},
+    events::DownloadEvent,
+    progress::ByteProgress,
+    verify::has_mp4_header,
+    DownloadOptions
+};
+
+// Downloads a single file over several connections, each writing its own byte range of a pre-allocated file
+
+// Below this, extra connections cost more than they save
+const MIN_SPLIT_SIZE: u64 = 1024 * 1024;
+
+// Length of the file at `url`, if the server accepts byte ranges for it and it's worth splitting
+pub(crate) async fn range_length(client: &reqwest::Client, url: Url) -> Option<u64> {
+    let response = client.head(url).send().await.ok()?
+        .error_for_status().ok()?;
+    let headers = response.headers();
+    let accepts_bytes = headers.get(ACCEPT_RANGES).is_some_and(|value| value == "bytes");
+    headers.get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|length| accepts_bytes && *length >= MIN_SPLIT_SIZE)
+}
+
+// Downloads `url` into `path` in `options.split` ranges, `length` comes from `range_length`
+pub(crate) async fn download_split(client: &reqwest::Client, clip_id: &str, url: Url, path: &Path, length: u64, options: &DownloadOptions) -> Result<()> {
+    let target = RangeTarget {
+        client,
+        clip_id,
+        url,
+        path,
+        options,
+        written: AtomicU64::new(0),
+        progress: Mutex::new(ByteProgress::new(options.byte_bar.as_ref()))
+    };
+    match options.timeout {
+        Some(limit) => timeout(limit, target.download(length)).await
+            .unwrap_or(Err(TwdlError::Timeout(limit))),
+        None => target.download(length).await
+    }
+}
+
+struct RangeTarget<'a> {
+    client: &'a reqwest::Client,
+
+    clip_id: &'a str,
+
+    url: Url,
+
+    path: &'a Path,
+
+    options: &'a DownloadOptions,
+
+    // Across all ranges, for progress events
+    written: AtomicU64,
+
+    progress: Mutex<ByteProgress<'a>>
+}
+
+impl RangeTarget<'_> {
+    async fn download(self, length: u64) -> Result<()> {
+        let file = File::create(self.path).await
+            .map_err(|err| TwdlError::io(format!("Failed to create file {}", self.path.display()), err))?;
+        file.set_len(length).await
+            .map_err(|err| TwdlError::io(format!("Failed to allocate file {}", self.path.display()), err))?;
+        drop(file);
+
+        emit(self.options, DownloadEvent::Started { clip_id: self.clip_id.to_string(), total_bytes: Some(length) }).await;
+        self.progress.lock().unwrap().start(Some(length));
+
+        let range_size = length.div_ceil(self.options.split.max(1) as u64);
+        let ranges = (0..length).step_by(range_size as usize)
+            .map(|start| self.download_range(start, (start + range_size).min(length) - 1));
+        try_join_all(ranges).await?;
+
+        let written = self.written.load(Ordering::Relaxed);
+        if written != length {
+            return Err(TwdlError::Range(format!("expected {length} bytes for clip {}, got {written}", self.clip_id)));
+        }
+        // Ranged responses skip the header check single-stream downloads do while writing
+        if !has_mp4_header(self.path).await? {
+            return Err(TwdlError::NotVideo(self.clip_id.to_string(), "missing mp4 header".to_string()));
+        }
+        self.progress.into_inner().unwrap().finish();
+        Ok(())
+    }
+
+    // `end` is inclusive, as in the Range header
+    async fn download_range(&self, start: u64, end: u64) -> Result<()> {
+        let response = self.client.get(self.url.clone())
+            .header(RANGE, format!("bytes={start}-{end}"))
+            .send().await?
+            .error_for_status()?;
+        // A 200 would be the whole file, not the range asked for
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(TwdlError::Range(format!("server answered a range request with {}", response.status())));
+        }
+
+        let mut output = OpenOptions::new().write(true).open(self.path).await
+            .map_err(|err| TwdlError::io(format!("Failed to open file {}", self.path.display()), err))?;
+        output.seek(SeekFrom::Start(start)).await
+            .map_err(|err| TwdlError::io(format!("Failed to seek in file {}", self.path.display()), err))?;
+
+        let mut stream = response.bytes_stream();
+        let mut remaining = end - start + 1;
+        while let Some(chunk) = stream.next().await {
+            let bytes = chunk?;
+            let len = bytes.len() as u64;
+            if len > remaining {
+                return Err(TwdlError::Range(format!("server sent more than bytes {start}-{end}")));
+            }
+            if let Some(limiter) = &self.options.rate_limiter {
+                limiter.acquire(bytes.len()).await;
+            }
+            output.write_all(&bytes).await
+                .map_err(|err| TwdlError::io(format!("Failed to write clip {}", self.clip_id), err))?;
+            remaining -= len;
+            self.progress.lock().unwrap().advance(len);
+            let written = self.written.fetch_add(len, Ordering::Relaxed) + len;
+            emit(self.options, DownloadEvent::Progress { clip_id: self.clip_id.to_string(), bytes: written }).await;
+        }
+        output.flush().await
+            .map_err(|err| TwdlError::io(format!("Failed to write clip {}", self.clip_id), err))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use twitch_api::twitch_oauth2::{AccessToken, AppAccessToken, TwitchToken, UserToken};
+
+use crate::{error::{Result, TwdlError}, private_file::write_private};
+
+// Tokens this close to expiring are re-fetched rather than reused
+const REFRESH_MARGIN: TimeDelta = TimeDelta::minutes(10);
+
+#[derive(Deserialize, Serialize)]
+struct CachedToken {
+    client_id: String,
+
+    access_token: String,
+
+    // Unix timestamp in seconds
+    expires_at: i64
+}
+
+// User tokens are kept whole, they're validated or refreshed against twitch when loaded
+#[derive(Deserialize, Serialize)]
+pub struct CachedUserToken {
+    pub client_id: String,
+
+    pub access_token: String,
+
+    pub refresh_token: Option<String>
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("twdl").join("token.json"))
+}
+
+fn user_cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("twdl").join("user_token.json"))
+}
+
+pub async fn load(client_id: &str, client_secret: &str) -> Option<AppAccessToken> {
+    let contents = fs::read_to_string(cache_path()?).await.ok()?;
+    let cached: CachedToken = serde_json::from_str(&contents).ok()?;
+    if cached.client_id != client_id {
+        return None;
+    }
+
+    let remaining = DateTime::from_timestamp(cached.expires_at, 0)? - Utc::now();
+    if remaining <= REFRESH_MARGIN {
+        return None;
+    }
+
+    Some(AppAccessToken::from_existing_unchecked(
+        AccessToken::new(cached.access_token),
+        None,
+        client_id.to_string(),
+        client_secret.to_string(),
+        None,
+        remaining.to_std().ok()
+    ))
+}
+
+pub async fn save(token: &AppAccessToken) -> Result<()> {
+    let path = cache_path().ok_or_else(|| TwdlError::io("No cache directory available", std::io::ErrorKind::NotFound.into()))?;
+    create_parent(&path).await?;
+
+    // An expiry too far out to represent is saved as already expired, so the token is just fetched again
+    let expires_in = TimeDelta::from_std(token.expires_in()).unwrap_or_default();
+    let cached = CachedToken {
+        client_id: token.client_id().to_string(),
+        access_token: token.token().secret().to_string(),
+        expires_at: (Utc::now() + expires_in).timestamp()
+    };
+    write_private(&path, &serde_json::to_string(&cached)?).await
+        .map_err(|err| TwdlError::io(format!("Failed to write token cache {}", path.display()), err))
+}
+
+pub async fn load_user(client_id: &str) -> Option<CachedUserToken> {
+    let contents = fs::read_to_string(user_cache_path()?).await.ok()?;
+    let cached: CachedUserToken = serde_json::from_str(&contents).ok()?;
+    (cached.client_id == client_id).then_some(cached)
+}
+
+pub async fn save_user(token: &UserToken) -> Result<()> {
+    let path = user_cache_path().ok_or_else(|| TwdlError::io("No cache directory available", std::io::ErrorKind::NotFound.into()))?;
+    create_parent(&path).await?;
+
+    let cached = CachedUserToken {
+        client_id: token.client_id().to_string(),
+        access_token: token.access_token.secret().to_string(),
+        refresh_token: token.refresh_token.as_ref().map(|refresh| refresh.secret().to_string())
+    };
+    write_private(&path, &serde_json::to_string(&cached)?).await
+        .map_err(|err| TwdlError::io(format!("Failed to write token cache {}", path.display()), err))
+}
+
+async fn create_parent(path: &Path) -> Result<()> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    fs::create_dir_all(parent).await
+        .map_err(|err| TwdlError::io(format!("Failed to create {}", parent.display()), err))
+}
@@ -1,11 +1,15 @@
-use std::{str::FromStr};
+use std::{str::FromStr, sync::Mutex, time::{Duration as StdDuration, SystemTime, UNIX_EPOCH}};
 
 use chrono::{DateTime, Duration, Utc};
 use futures_util::future::join_all;
-use twitch_api::{helix::{clips::{get_clips, Clip}, users::{GetUsersRequest, User}}, twitch_oauth2::AppAccessToken, types::UserId, HelixClient};
-use anyhow::Result;
-use twitch_types::Timestamp;
-use log::error;
+use serde::de::DeserializeOwned;
+use tokio::{sync::RwLock, time::sleep};
+use twitch_api::{helix::{clips::{get_clips, Clip}, games::GetGamesRequest, users::{GetUsersRequest, User}, ClientRequestError, Request, RequestGet, Response}, twitch_oauth2::{tokens::errors::AppAccessTokenError, AccessToken, AppAccessToken, ClientSecret, DeviceUserTokenBuilder, RefreshToken, TwitchToken, UserToken}, types::{CategoryId, UserId}, HelixClient};
+use twitch_types::{Cursor, Timestamp};
+use log::{debug, error, warn};
+use reqwest::header::HeaderMap;
+
+use crate::{error::{Result, TwdlError}, token_cache, user_cache};
 
 pub fn convert_dt(input: &DateTime<Utc>) -> Timestamp {
     match Timestamp::from_str(&input.to_rfc3339()) {
@@ -18,6 +22,12 @@ pub fn convert_ts(input: &Timestamp) -> DateTime<Utc> {
     input.as_str().parse::<DateTime<Utc>>().expect("Invalid timestamp format")
 }
 
+// What to list clips for, helix accepts exactly one of these per request
+pub enum ClipSource {
+    Broadcaster(UserId),
+    Game(CategoryId)
+}
+
 pub enum DateChunkingType {
     ByDuration(Duration),
     ByNumber(u16)
@@ -41,27 +51,305 @@ fn split_date_range(start: DateTime<Utc>, end: DateTime<Utc>, chunking_type: Dat
     chunks
 }
 
-pub async fn get_token(client_id: &str, client_secret: &str) -> Result<AppAccessToken> {
-    let client: HelixClient<reqwest::Client> = HelixClient::default();
-    Ok(AppAccessToken::get_app_access_token(
-        &client,
-        client_id.into(),
-        client_secret.into(),
-        vec![/* scopes */],
-    )
-    .await?)
+pub async fn get_token(client: &HelixClient<'_, reqwest::Client>, client_id: &str, client_secret: &str) -> Result<AppAccessToken> {
+    if let Some(token) = token_cache::load(client_id, client_secret).await {
+        debug!("Using cached application token");
+        return Ok(token);
+    }
+    fetch_token(client, client_id, client_secret).await
+}
+
+// Extra attempts at fetching a token after a network error, rejected credentials aren't retried
+const TOKEN_RETRIES: u32 = 3;
+const TOKEN_BACKOFF: StdDuration = StdDuration::from_secs(1);
+
+// Always asks twitch for a new token, bypassing the cache
+async fn fetch_token(client: &HelixClient<'_, reqwest::Client>, client_id: &str, client_secret: &str) -> Result<AppAccessToken> {
+    let mut attempt = 0;
+    let token = loop {
+        let result = AppAccessToken::get_app_access_token(
+            client,
+            client_id.into(),
+            client_secret.into(),
+            vec![/* scopes */],
+        )
+        .await;
+        match result {
+            Ok(token) => break token,
+            Err(AppAccessTokenError::Request(err)) if attempt < TOKEN_RETRIES => {
+                let wait = TOKEN_BACKOFF * 2u32.pow(attempt);
+                warn!("Failed to reach twitch for a token ({err}), retrying in {}s", wait.as_secs());
+                sleep(wait).await;
+                attempt += 1;
+            }
+            Err(AppAccessTokenError::Request(err)) => {
+                return Err(TwdlError::Auth(format!("network error contacting Twitch ({err})")));
+            }
+            // Twitch answered, but not with a token, which means the credentials were refused
+            Err(err) => return Err(TwdlError::Auth(format!("invalid client credentials, check the client ID and secret ({err})")))
+        }
+    };
+
+    if let Err(err) = token_cache::save(&token).await {
+        warn!("Failed to cache application token: {err}");
+    }
+    Ok(token)
+}
+
+// Loads a cached user token, refreshing it if twitch no longer accepts it, otherwise runs the device code flow.
+// `prompt` gets the URL to open and the code to enter there.
+async fn get_user_token<F>(client: &HelixClient<'_, reqwest::Client>, client_id: &str, client_secret: &str, prompt: F) -> Result<UserToken>
+where
+    F: FnOnce(&str, &str)
+{
+    let secret = || ClientSecret::new(client_secret.to_string());
+    if let Some(cached) = token_cache::load_user(client_id).await {
+        let refresh = cached.refresh_token.clone().map(RefreshToken::new);
+        if let Ok(token) = UserToken::from_existing(client, AccessToken::new(cached.access_token), refresh.clone(), secret()).await {
+            debug!("Using cached user token");
+            return Ok(token);
+        }
+        let refreshed = match refresh {
+            Some(refresh) => UserToken::from_refresh_token(client, refresh, client_id.into(), secret()).await.ok(),
+            None => None
+        };
+        if let Some(token) = refreshed {
+            save_user_token(&token).await;
+            return Ok(token);
+        }
+    }
+
+    let mut builder = DeviceUserTokenBuilder::new(client_id, vec![/* scopes */]);
+    builder.set_secret(Some(secret()));
+    let (uri, code) = {
+        let response = builder.start(client).await
+            .map_err(|err| TwdlError::Auth(format!("failed to start the device code flow ({err})")))?;
+        (response.verification_uri.clone(), response.user_code.clone())
+    };
+    prompt(&uri, &code);
+    let token = builder.wait_for_code(client, sleep).await
+        .map_err(|err| TwdlError::Auth(format!("user authorization didn't complete ({err})")))?;
+    save_user_token(&token).await;
+    Ok(token)
+}
+
+async fn save_user_token(token: &UserToken) {
+    if let Err(err) = token_cache::save_user(token).await {
+        warn!("Failed to cache user token: {err}");
+    }
+}
+
+#[derive(Clone)]
+enum SessionToken {
+    App(AppAccessToken),
+
+    // Authorized by a twitch user, see `TwitchSession::with_user_token`
+    User(UserToken)
+}
+
+impl SessionToken {
+    fn secret(&self) -> &str {
+        match self {
+            SessionToken::App(token) => token.token().secret(),
+            SessionToken::User(token) => token.token().secret()
+        }
+    }
+}
+
+fn header_number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+// Helix sends the time its rate limit bucket refills as unix seconds
+fn rate_limit_reset(headers: &HeaderMap) -> Option<SystemTime> {
+    header_number(headers, "Ratelimit-Reset").map(|seconds| UNIX_EPOCH + StdDuration::from_secs(seconds))
+}
+
+fn until(time: SystemTime) -> StdDuration {
+    time.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+// Fallback waits for a 429 without a Ratelimit-Reset header. Helix refills its bucket over a minute,
+// these add up to roughly that.
+const RATE_LIMIT_RETRIES: u32 = 5;
+const RATE_LIMIT_BACKOFF: StdDuration = StdDuration::from_secs(2);
+
+// How one helix request went, as far as retrying it is concerned
+enum Attempt<T> {
+    Done(T),
+
+    // 401, the token expired or was revoked
+    Unauthorized(TwdlError),
+
+    // 429, `reset` is how long until the bucket refills when helix said so
+    RateLimited {
+        error: TwdlError,
+
+        reset: Option<StdDuration>
+    }
+}
+
+// Runs `send` until it gets through, swapping in a token from `refresh` the first time one is rejected
+// and waiting out rate limits. Generic over the token so the retrying can be exercised without twitch.
+async fn retry_helix<K, T, S, SF, F, FF>(mut token: K, send: S, refresh: F) -> Result<T>
+where
+    K: Clone,
+    S: Fn(K) -> SF,
+    SF: Future<Output = Result<Attempt<T>>>,
+    F: Fn(K) -> FF,
+    FF: Future<Output = Result<K>>
+{
+    let mut refreshed = false;
+    let mut rate_limited = 0;
+    loop {
+        match send(token.clone()).await? {
+            Attempt::Done(response) => return Ok(response),
+            Attempt::Unauthorized(_) if !refreshed => {
+                warn!("Token was rejected, fetching a new one");
+                token = refresh(token).await?;
+                refreshed = true;
+            }
+            Attempt::RateLimited { reset, .. } if rate_limited < RATE_LIMIT_RETRIES => {
+                let wait = reset.unwrap_or_else(|| RATE_LIMIT_BACKOFF * 2u32.pow(rate_limited));
+                rate_limited += 1;
+                warn!("Rate limited by twitch, retrying in {}s", wait.as_secs());
+                sleep(wait).await;
+            }
+            Attempt::Unauthorized(error) | Attempt::RateLimited { error, .. } => return Err(error)
+        }
+    }
+}
+
+// Helix client plus the credentials needed to replace its token if it expires mid-run
+pub struct TwitchSession {
+    client: HelixClient<'static, reqwest::Client>,
+    // Sends helix GETs directly, so the rate limit headers can be read
+    http: reqwest::Client,
+    client_id: String,
+    client_secret: String,
+    token: RwLock<SessionToken>,
+    // When helix last said the bucket was empty, requests hold off until it refills
+    exhausted_until: Mutex<Option<SystemTime>>
 }
 
-pub async fn get_clips_chunked(broadcaster_id: &UserId,
-                        token: &AppAccessToken,
+impl TwitchSession {
+    pub async fn new(client: reqwest::Client, client_id: &str, client_secret: &str) -> Result<Self> {
+        let http = client.clone();
+        let client = HelixClient::with_client(client);
+        let token = get_token(&client, client_id, client_secret).await?;
+        Ok(TwitchSession {
+            client,
+            http,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            token: RwLock::new(SessionToken::App(token)),
+            exhausted_until: Mutex::new(None)
+        })
+    }
+
+    // Acts as the user who authorizes the app, for data an app token can't see
+    pub async fn with_user_token<F>(client: reqwest::Client, client_id: &str, client_secret: &str, prompt: F) -> Result<Self>
+    where
+        F: FnOnce(&str, &str)
+    {
+        let http = client.clone();
+        let client = HelixClient::with_client(client);
+        let token = get_user_token(&client, client_id, client_secret, prompt).await?;
+        Ok(TwitchSession {
+            client,
+            http,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            token: RwLock::new(SessionToken::User(token)),
+            exhausted_until: Mutex::new(None)
+        })
+    }
+
+    // GET a helix endpoint, re-authenticating once if the token is rejected and waiting out rate limits
+    async fn req_get<R, D>(&self, request: R) -> Result<Response<R, D>>
+    where
+        R: Request<Response = D> + RequestGet + Clone,
+        D: DeserializeOwned + PartialEq
+    {
+        let token = self.token.read().await.clone();
+        retry_helix(
+            token,
+            |token| self.send_get(request.clone(), token),
+            |rejected| async move { self.refresh(&rejected).await }
+        ).await
+    }
+
+    async fn send_get<R, D>(&self, request: R, token: SessionToken) -> Result<Attempt<Response<R, D>>>
+    where
+        R: Request<Response = D> + RequestGet,
+        D: DeserializeOwned + PartialEq
+    {
+        let exhausted_until = *self.exhausted_until.lock().unwrap();
+        let wait = exhausted_until.map(until).unwrap_or_default();
+        if !wait.is_zero() {
+            debug!("Rate limit used up, waiting {}s for it to reset", wait.as_secs());
+            sleep(wait).await;
+        }
+
+        let http_request = request.create_request(token.secret(), &self.client_id)
+            .map_err(ClientRequestError::<reqwest::Error>::from)?;
+        let uri = http_request.uri().clone();
+        let response = self.http.execute(http_request.map(|body| body.to_vec()).try_into()?).await?;
+
+        let reset = rate_limit_reset(response.headers());
+        let remaining = header_number(response.headers(), "Ratelimit-Remaining");
+        *self.exhausted_until.lock().unwrap() = match remaining {
+            Some(0) => reset,
+            _ => None
+        };
+
+        let status = response.status();
+        let body = response.bytes().await?.to_vec();
+        let response = http::Response::builder().status(status).body(body)
+            .expect("status came from a response");
+        let err: TwdlError = match R::parse_response(Some(request), &uri, response) {
+            Ok(response) => return Ok(Attempt::Done(response)),
+            Err(err) => ClientRequestError::<reqwest::Error>::HelixRequestGetError(err).into()
+        };
+        match status.as_u16() {
+            401 => Ok(Attempt::Unauthorized(err)),
+            429 => Ok(Attempt::RateLimited { error: err, reset: reset.map(until) }),
+            _ => Err(err)
+        }
+    }
+
+    async fn refresh(&self, rejected: &SessionToken) -> Result<SessionToken> {
+        let mut token = self.token.write().await;
+        // Concurrent requests all see the 401, only the first one needs to fetch
+        if token.secret() != rejected.secret() {
+            return Ok(token.clone());
+        }
+        let refreshed = match &*token {
+            SessionToken::App(_) => SessionToken::App(fetch_token(&self.client, &self.client_id, &self.client_secret).await?),
+            SessionToken::User(user) => {
+                let mut user = user.clone();
+                user.refresh_token(&self.client).await
+                    .map_err(|err| TwdlError::Auth(format!("failed to refresh the user token, authorize again with --auth user ({err})")))?;
+                save_user_token(&user).await;
+                SessionToken::User(user)
+            }
+        };
+        *token = refreshed;
+        Ok(token.clone())
+    }
+}
+
+pub async fn get_clips_chunked(source: &ClipSource,
+                        session: &TwitchSession,
                         start: DateTime<Utc>,
                         end: DateTime<Utc>,
                         chunking_type: DateChunkingType,
-                        first: Option<usize>) -> Vec<Clip> {
+                        first: Option<usize>,
+                        page_delay: StdDuration) -> Vec<Clip> {
     let date_ranges = split_date_range(start, end, chunking_type);
     let futures = date_ranges
         .iter()
-        .map(|chunk| get_clips(broadcaster_id, token, chunk.0.clone(), chunk.1.clone(), first));
+        .map(|chunk| get_clips(source, session, chunk.0.clone(), chunk.1.clone(), first, page_delay));
 
     let mut clips = Vec::new();
 
@@ -79,62 +367,189 @@ pub async fn get_clips_chunked(broadcaster_id: &UserId,
     clips
 }
 
-async fn get_clips(broadcaster_id: &UserId,
-                    token: &AppAccessToken,
+async fn get_clips(source: &ClipSource,
+                    session: &TwitchSession,
                     started_at: Timestamp,
                     ended_at: Timestamp,
-                    first: Option<usize>) -> Result<Vec<Clip>> {
-    let client: HelixClient<reqwest::Client> = HelixClient::default();
+                    first: Option<usize>,
+                    page_delay: StdDuration) -> Result<Vec<Clip>> {
     let mut clips = Vec::new();
     let mut cursor = None;
+    loop {
+        let (page, next_cursor) = get_clips_page(source, session, &started_at, &ended_at, first, cursor).await?;
+        clips.extend(page);
 
+        let Some(next_cursor) = next_cursor else {
+            break;
+        };
+        cursor = Some(next_cursor);
+        // Only spaces out the pages of one date range, the ranges themselves are still fetched together
+        if !page_delay.is_zero() {
+            sleep(page_delay).await;
+        }
+    }
+    Ok(clips)
+}
+
+// One page of clips and the cursor for the next, None once there are no more.
+// Pass the returned cursor back in to continue, e.g. to resume paging from a saved position.
+pub async fn get_clips_page(source: &ClipSource,
+                            session: &TwitchSession,
+                            started_at: &Timestamp,
+                            ended_at: &Timestamp,
+                            first: Option<usize>,
+                            cursor: Option<Cursor>) -> Result<(Vec<Clip>, Option<Cursor>)> {
     let mut request = get_clips::GetClipsRequest::builder()
-        .broadcaster_id(broadcaster_id.as_cow())
         .started_at(Some(started_at.as_cow()))
         .ended_at(Some(ended_at.as_cow()))
         .first(first)
         .build();
+    match source {
+        ClipSource::Broadcaster(id) => request.broadcaster_id = Some(id.as_cow()),
+        ClipSource::Game(id) => request.game_id = Some(id.as_cow())
+    }
+    request.after = cursor.map(Into::into);
 
+    let response = session.req_get(request).await?;
+    Ok((response.data, response.pagination))
+}
 
-    loop {
-        request.after = cursor.clone();
+pub async fn get_broadcaster_id(login: &str, session: &TwitchSession) -> Result<Option<UserId>> {
+    if let Some(id) = user_cache::load(login).await {
+        debug!("Using cached id {id} for {login}");
+        return Ok(Some(id));
+    }
 
-        let response = client.req_get(request.clone(), token).await?;
-        clips.extend(response.data);
+    let request = GetUsersRequest::builder()
+        .login(vec![login].into())
+        .build();
 
-        if let Some(next_cursor) = response.pagination {
-            cursor = Some(next_cursor.into());
-        } else {
-            break;
-        }
+    let response = session.req_get(request).await?;
+    let id = response.data.first().map(|user| user.id.clone());
+    let saved = match &id {
+        Some(id) => user_cache::save(login, id).await,
+        None => Ok(())
+    };
+    if let Err(err) = saved {
+        warn!("Failed to cache user id: {err}");
     }
-    Ok(clips)
+    Ok(id)
 }
 
-pub async fn get_broadcaster_id(login: &String, token: &AppAccessToken) -> Result<Option<UserId>> {
-    let client: HelixClient<reqwest::Client> = HelixClient::default();
-    let user_option = client.get_user_from_login(login, token).await?;
+pub async fn get_game_id(name: &str, session: &TwitchSession) -> Result<Option<CategoryId>> {
+    let request = GetGamesRequest::builder()
+        .name(vec![name].into())
+        .build();
 
-    Ok(user_option.map(|user| user.id))
+    let response = session.req_get(request).await?;
+    Ok(response.data.first().map(|game| game.id.clone()))
 }
 
-pub async fn get_clip(clip_id: &String, token: &AppAccessToken) -> Result<Option<Clip>> {
-    let client: HelixClient<reqwest::Client> = HelixClient::default();
+pub async fn get_clip(clip_id: &str, session: &TwitchSession) -> Result<Option<Clip>> {
     let get_clip_request = get_clips::GetClipsRequest::builder()
         .id(vec![clip_id].into())
         .build();
-    let response = client.req_get(get_clip_request, token).await?;
+    let response = session.req_get(get_clip_request).await?;
     let clip = response.data.first();
     Ok(clip.cloned())
 }
 
-pub async fn get_user(user_id: &UserId, token: &AppAccessToken) -> Result<Option<User>> {
-    let client: HelixClient<reqwest::Client> = HelixClient::default();
+pub async fn get_user(user_id: &UserId, session: &TwitchSession) -> Result<Option<User>> {
     let request = GetUsersRequest::builder()
         .id(user_id)
         .build();
 
-    let response = client.req_get(request, token).await?;
+    let response = session.req_get(request).await?;
     Ok(response.data.first().cloned())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_once_with_a_refreshed_token() {
+        let sent = AtomicU32::new(0);
+        let refreshed = AtomicU32::new(0);
+        let result = retry_helix(
+            "expired".to_string(),
+            |token| {
+                sent.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match token == "expired" {
+                        true => Ok(Attempt::Unauthorized(TwdlError::Auth("401".to_string()))),
+                        false => Ok(Attempt::Done(token))
+                    }
+                }
+            },
+            |_| {
+                refreshed.fetch_add(1, Ordering::SeqCst);
+                async { Ok("fresh".to_string()) }
+            }
+        ).await;
+        assert_eq!(result.unwrap(), "fresh");
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+        assert_eq!(refreshed.load(Ordering::SeqCst), 1);
+    }
+
+    fn rate_limited(reset: Option<StdDuration>) -> Result<Attempt<u32>> {
+        Ok(Attempt::RateLimited { error: TwdlError::Gql("429".to_string()), reset })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_until_the_rate_limit_resets() {
+        let sent = AtomicU32::new(0);
+        let started = tokio::time::Instant::now();
+        let result = retry_helix(
+            (),
+            |()| {
+                let attempt = sent.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match attempt {
+                        0 => rate_limited(Some(StdDuration::from_secs(30))),
+                        _ => Ok(Attempt::Done(attempt))
+                    }
+                }
+            },
+            |()| async { Ok(()) }
+        ).await;
+        assert_eq!(result.unwrap(), 1);
+        let waited = started.elapsed();
+        assert!(waited >= StdDuration::from_secs(30) && waited < StdDuration::from_secs(31), "{waited:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_without_a_reset_and_gives_up() {
+        let started = tokio::time::Instant::now();
+        let result = retry_helix((), |()| async { rate_limited(None) }, |()| async { Ok(()) }).await;
+        assert!(matches!(result, Err(TwdlError::Gql(_))));
+        // 2 + 4 + 8 + 16 + 32
+        assert!(started.elapsed() >= StdDuration::from_secs(62));
+    }
+
+    #[test]
+    fn reads_the_rate_limit_reset() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_reset(&headers), None);
+        headers.insert("Ratelimit-Reset", "1700000000".parse().unwrap());
+        assert_eq!(rate_limit_reset(&headers), Some(UNIX_EPOCH + StdDuration::from_secs(1_700_000_000)));
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_new_token_is_rejected_too() {
+        let sent = AtomicU32::new(0);
+        let result: Result<()> = retry_helix(
+            "expired".to_string(),
+            |_| {
+                sent.fetch_add(1, Ordering::SeqCst);
+                async { Ok(Attempt::Unauthorized(TwdlError::Auth("401".to_string()))) }
+            },
+            |_| async { Ok("fresh".to_string()) }
+        ).await;
+        assert!(matches!(result, Err(TwdlError::Auth(_))));
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,69 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use twitch_api::types::UserId;
+
+use crate::error::{Result, TwdlError};
+
+// Broadcaster login to user id lookups, kept in the OS cache dir so repeat runs skip the helix call.
+// Logins can be renamed and later taken by someone else, so entries expire.
+
+const USER_TTL: TimeDelta = TimeDelta::days(7);
+
+#[derive(Deserialize, Serialize)]
+struct CachedUser {
+    id: String,
+
+    // Unix timestamp in seconds
+    cached_at: i64
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("twdl").join("users.json"))
+}
+
+async fn read_all() -> HashMap<String, CachedUser> {
+    let Some(path) = cache_path() else {
+        return HashMap::new();
+    };
+    fs::read(path).await.ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+// Logins are case insensitive
+pub async fn load(login: &str) -> Option<UserId> {
+    let users = read_all().await;
+    let cached = users.get(&login.to_lowercase())?;
+    let age = Utc::now() - DateTime::from_timestamp(cached.cached_at, 0)?;
+    (age < USER_TTL).then(|| cached.id.clone().into())
+}
+
+pub async fn save(login: &str, id: &UserId) -> Result<()> {
+    let path = cache_path().ok_or_else(|| TwdlError::io("No cache directory available", std::io::ErrorKind::NotFound.into()))?;
+    let mut users = read_all().await;
+    let now = Utc::now();
+    users.retain(|_, cached| DateTime::from_timestamp(cached.cached_at, 0).is_some_and(|at| now - at < USER_TTL));
+    users.insert(login.to_lowercase(), CachedUser { id: id.to_string(), cached_at: now.timestamp() });
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await
+            .map_err(|err| TwdlError::io(format!("Failed to create {}", parent.display()), err))?;
+    }
+    fs::write(&path, serde_json::to_string(&users)?).await
+        .map_err(|err| TwdlError::io(format!("Failed to write user cache {}", path.display()), err))
+}
+
+pub async fn clear() -> Result<()> {
+    let Some(path) = cache_path() else {
+        return Ok(());
+    };
+    match fs::remove_file(&path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(TwdlError::io(format!("Failed to remove user cache {}", path.display()), err))
+        }
+        _ => Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+
+use futures_util::future::join_all;
+use regex::Regex;
+use serde::Deserialize;
+use tokio::{fs, io::AsyncReadExt};
+
+use crate::{
+    checksum::{hash_file, read_sidecar},
+    error::{Result, TwdlError},
+    manifest::MANIFEST_FILE,
+    clip_path, get_video_source_files, head_content_length, looks_like_mp4, select_source_file, DownloadOptions, GqlConfig, CLIP_EXTENSION
+};
+
+// Checks previously downloaded clips against their checksum sidecar, if any, and the size twitch reports for their source file
+
+#[derive(Debug)]
+pub enum VerifyStatus {
+    Ok,
+
+    Missing,
+
+    SizeMismatch { expected: u64, actual: u64 },
+
+    // The file doesn't start with an mp4 `ftyp` box, usually a saved error page
+    NotVideo,
+
+    // The file no longer matches the `.sha256` sidecar written when it was downloaded
+    ChecksumMismatch,
+
+    Error(String)
+}
+
+#[derive(Debug)]
+pub struct VerifyResult {
+    pub clip_id: String,
+
+    pub path: PathBuf,
+
+    pub status: VerifyStatus
+}
+
+// A downloaded clip file to check
+#[derive(Debug, Clone)]
+pub struct ClipFile {
+    pub clip_id: String,
+
+    pub path: PathBuf
+}
+
+// Only the id and path are read, so metadata files and arrays of full clips both work as a manifest
+#[derive(Deserialize)]
+struct ManifestEntry {
+    id: String,
+
+    // Written by `download_clips`, the file may be numbered or renamed
+    #[serde(default)]
+    path: Option<PathBuf>
+}
+
+// Clip files listed in a json array of objects with an `id` and optionally a `path`, looked for in `directory`
+pub async fn read_manifest(path: &Path, directory: &Path) -> Result<Vec<ClipFile>> {
+    let contents = fs::read(path).await
+        .map_err(|err| TwdlError::io(format!("Failed to read manifest {}", path.display()), err))?;
+    let entries: Vec<ManifestEntry> = serde_json::from_slice(&contents)?;
+    Ok(entries.into_iter()
+        .filter_map(|entry| locate(directory, entry.id, entry.path.as_deref()))
+        .collect())
+}
+
+// Only the file name of a recorded path is used, so a moved directory still checks out.
+// Entries that aren't clip files, like audio kept after --delete-video, are left out.
+fn locate(directory: &Path, clip_id: String, path: Option<&Path>) -> Option<ClipFile> {
+    let path = match path.and_then(Path::file_name) {
+        Some(name) => directory.join(name),
+        None => clip_path(directory, &clip_id)
+    };
+    path.extension().is_some_and(|ext| ext == CLIP_EXTENSION).then_some(ClipFile { clip_id, path })
+}
+
+// Clip files recorded in `directory`'s manifest, or every clip file in it when there is no manifest
+pub async fn find_clips(directory: &Path) -> Result<Vec<ClipFile>> {
+    let manifest = directory.join(MANIFEST_FILE);
+    if fs::try_exists(&manifest).await.unwrap_or(false) {
+        return read_manifest(&manifest, directory).await;
+    }
+
+    let mut dir = fs::read_dir(directory).await
+        .map_err(|err| TwdlError::io(format!("Failed to read directory {}", directory.display()), err))?;
+    let mut clips = Vec::new();
+    while let Some(entry) = dir.next_entry().await
+        .map_err(|err| TwdlError::io(format!("Failed to read directory {}", directory.display()), err))? {
+        let path = entry.path();
+        let clip_id = path.file_stem()
+            .filter(|_| path.extension().is_some_and(|ext| ext == CLIP_EXTENSION))
+            .and_then(|stem| clip_id_from_stem(&stem.to_string_lossy()));
+        if let Some(clip_id) = clip_id {
+            clips.push(ClipFile { clip_id, path });
+        }
+    }
+    clips.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(clips)
+}
+
+// The clip id in a file name twdl writes, e.g. `007_{id}` with --number.
+// None for files made from a clip, `{id}_trimmed` and the `{id}-720p60` files of --all-qualities.
+fn clip_id_from_stem(stem: &str) -> Option<String> {
+    let re = Regex::new(r"^(?:\d+_)?(.+?)(_trimmed|-\d+p\d+)?$").expect("Failed to parse regex string");
+    let caps = re.captures(stem)?;
+    match caps.get(2) {
+        Some(_) => None,
+        None => Some(caps[1].to_string())
+    }
+}
+
+pub async fn verify_clips(client: &reqwest::Client, clips: Vec<ClipFile>, gql: &GqlConfig, options: &DownloadOptions, probe: bool) -> Vec<VerifyResult> {
+    let mut results = Vec::with_capacity(clips.len());
+    for chunk in clips.chunks(options.concurrency) {
+        let futures: Vec<_> = chunk.iter().map(|clip| async move {
+            let status = verify_clip(client, &clip.clip_id, &clip.path, gql, options, probe).await
+                .unwrap_or_else(|err| VerifyStatus::Error(err.to_string()));
+            VerifyResult { clip_id: clip.clip_id.clone(), path: clip.path.clone(), status }
+        }).collect();
+        results.extend(join_all(futures).await);
+    }
+    results
+}
+
+async fn verify_clip(client: &reqwest::Client, clip_id: &str, path: &Path, gql: &GqlConfig, options: &DownloadOptions, probe: bool) -> Result<VerifyStatus> {
+    let actual = match fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Ok(VerifyStatus::Missing)
+    };
+
+    if probe && !has_mp4_header(path).await? {
+        return Ok(VerifyStatus::NotVideo);
+    }
+
+    let checksum_matches = match read_sidecar(path).await {
+        Some(expected) => hash_file(path).await? == expected,
+        None => true
+    };
+    if !checksum_matches {
+        return Ok(VerifyStatus::ChecksumMismatch);
+    }
+
+    let files = get_video_source_files(client, clip_id, gql).await?;
+    let source = select_source_file(&files, options.quality)
+        .ok_or_else(|| TwdlError::NoSourceFiles(clip_id.to_string()))?;
+    let expected = head_content_length(client, source.url.clone()).await?;
+
+    Ok(match expected {
+        Some(expected) if expected != actual => VerifyStatus::SizeMismatch { expected, actual },
+        _ => VerifyStatus::Ok
+    })
+}
+
+pub(crate) async fn has_mp4_header(path: &Path) -> Result<bool> {
+    let mut file = fs::File::open(path).await
+        .map_err(|err| TwdlError::io(format!("Failed to open {}", path.display()), err))?;
+    let mut header = [0u8; 12];
+    let mut read = 0;
+    while read < header.len() {
+        let n = file.read(&mut header[read..]).await
+            .map_err(|err| TwdlError::io(format!("Failed to read {}", path.display()), err))?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    Ok(looks_like_mp4(&header[..read]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_clip_id_from_file_names() {
+        assert_eq!(clip_id_from_stem("AwkwardHelplessSalamander").as_deref(), Some("AwkwardHelplessSalamander"));
+        assert_eq!(clip_id_from_stem("007_AwkwardHelplessSalamander").as_deref(), Some("AwkwardHelplessSalamander"));
+        assert_eq!(clip_id_from_stem("Brave-4Bq9Hn6R_bGzN").as_deref(), Some("Brave-4Bq9Hn6R_bGzN"));
+        assert_eq!(clip_id_from_stem("AwkwardHelplessSalamander-720p60"), None);
+        assert_eq!(clip_id_from_stem("AwkwardHelplessSalamander_trimmed"), None);
+    }
+
+    #[tokio::test]
+    async fn finds_clips_through_the_manifest() {
+        let directory = tempfile::tempdir().unwrap();
+        let manifest = r#"[
+            { "id": "FirstClip", "path": "/elsewhere/001_FirstClip.mp4", "size": 1, "downloaded_at": "2024-01-01T00:00:00Z" },
+            { "id": "SecondClip", "path": "/elsewhere/002_SecondClip.mp3", "size": 1, "downloaded_at": "2024-01-01T00:00:00Z" }
+        ]"#;
+        std::fs::write(directory.path().join(MANIFEST_FILE), manifest).unwrap();
+        std::fs::write(directory.path().join("FirstClip_trimmed.mp4"), b"").unwrap();
+
+        let clips = find_clips(directory.path()).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].clip_id, "FirstClip");
+        assert_eq!(clips[0].path, directory.path().join("001_FirstClip.mp4"));
+    }
+
+    #[tokio::test]
+    async fn skips_derived_files_without_a_manifest() {
+        let directory = tempfile::tempdir().unwrap();
+        for name in ["007_FirstClip.mp4", "FirstClip-720p60.mp4", "FirstClip_trimmed.mp4", "FirstClip.jpg"] {
+            std::fs::write(directory.path().join(name), b"").unwrap();
+        }
+
+        let clips = find_clips(directory.path()).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].clip_id, "FirstClip");
+        assert_eq!(clips[0].path, directory.path().join("007_FirstClip.mp4"));
+    }
+}
@@ -10,10 +10,30 @@ pub struct VideoSourceResponse {
     pub extensions: Extensions
 }
 
+// Loose shape checked before `VideoSourceResponse`, so errors and unknown slugs don't surface as parse failures
+#[derive(Deserialize)]
+pub struct ErrorResponse {
+    #[serde(default)]
+    pub errors: Vec<GqlError>,
+
+    pub data: Option<NullableData>
+}
+
+#[derive(Deserialize)]
+pub struct GqlError {
+    pub message: String
+}
+
+#[derive(Deserialize)]
+pub struct NullableData {
+    pub clip: Option<serde::de::IgnoredAny>
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Data {
-    pub clip: Clip
+    // Null when the clip is unavailable
+    pub clip: Option<Clip>
 }
 
 #[derive(Deserialize, Serialize)]
@@ -21,6 +41,10 @@ pub struct Data {
 pub struct Clip {
     pub playback_access_token: PlaybackAccessToken,
 
+    // Whole seconds, `Extensions::duration_milliseconds` is how long the request took, not the clip
+    #[serde(default)]
+    pub duration_seconds: Option<u64>,
+
     pub video_qualities: Vec<VideoQuality>
 }
 
@@ -52,4 +76,18 @@ pub struct Extensions {
 
     #[serde(rename = "requestID")]
     pub request_id: String
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_clip_deserializes() {
+        let body = r#"{
+            "data": { "clip": null },
+            "extensions": { "durationMilliseconds": 3, "operationName": "VideoAccessToken_Clip", "requestID": "abc" }
+        }"#;
+        let response: VideoSourceResponse = serde_json::from_str(body).unwrap();
+        assert!(response.data.clip.is_none());
+    }
+}
@@ -0,0 +1,41 @@
+use indicatif::HumanBytes;
+use serde_json::json;
+
+use crate::{error::Result, report::{DownloadReport, RunSummary}};
+
+// Completion notifications posted once a run finishes
+
+// Discord rejects messages over 2000 characters, so only this many failed ids are listed
+const DISCORD_MAX_FAILED_IDS: usize = 20;
+
+pub async fn notify_discord(client: &reqwest::Client, url: &str, broadcaster: &str, report: &DownloadReport) -> Result<()> {
+    let size = report.total_bytes();
+    let mut content = format!(
+        "**{broadcaster}**: downloaded {} clips ({}), skipped {}, {} unavailable, {} failed",
+        report.downloaded.len(), HumanBytes(size), report.skipped, report.unavailable.len(), report.failed.len()
+    );
+    if !report.failed.is_empty() {
+        let ids: Vec<&str> = report.failed.iter().take(DISCORD_MAX_FAILED_IDS).map(|clip| clip.clip_id.as_str()).collect();
+        content.push_str(&format!("\nFailed: {}", ids.join(", ")));
+        if report.failed.len() > DISCORD_MAX_FAILED_IDS {
+            content.push_str(&format!(" and {} more", report.failed.len() - DISCORD_MAX_FAILED_IDS));
+        }
+    }
+
+    client.post(url)
+        .json(&json!({ "content": content }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// Posts `payload` as json to any endpoint, `headers` are sent as is (e.g. for an Authorization token)
+pub async fn notify(client: &reqwest::Client, url: &str, headers: &[(String, String)], payload: &RunSummary<'_>) -> Result<()> {
+    let mut request = client.post(url).json(payload);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
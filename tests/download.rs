@@ -0,0 +1,201 @@
+use std::{path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use reqwest::Url;
+use serde_json::json;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener, time::Instant};
+use twdl::{download_file, download_slugs, error::TwdlError, rate_limit::RateLimiter, DownloadOptions, GqlConfig};
+use wiremock::{matchers::{method, path}, Mock, MockServer, ResponseTemplate};
+
+// Starts with an `ftyp` box so it passes the mp4 header check
+const CLIP_BODY: &[u8] = b"\x00\x00\x00\x18ftypmp42 rest of the clip";
+
+fn options() -> DownloadOptions {
+    DownloadOptions { retries: 0, retry_passes: 0, ..DownloadOptions::default() }
+}
+
+// Answers every connection with `response` as is, for responses a well-behaved server wouldn't send.
+// The connection is closed afterwards unless `hold_open`, which leaves the client waiting for the rest.
+async fn raw_server(response: Vec<u8>, hold_open: bool) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let response = response.clone();
+            tokio::spawn(async move {
+                let mut request = [0; 4096];
+                let _ = socket.read(&mut request).await;
+                let _ = socket.write_all(&response).await;
+                if hold_open {
+                    std::future::pending::<()>().await;
+                }
+            });
+        }
+    });
+    Url::parse(&format!("http://{address}/clip.mp4")).unwrap()
+}
+
+fn response_with_headers(headers: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\n{headers}\r\n").into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn part_path(file: &Path) -> PathBuf {
+    let mut part = file.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+fn gql(server: &MockServer) -> GqlConfig {
+    GqlConfig { endpoint: format!("{}/gql", server.uri()), ..GqlConfig::default() }
+}
+
+// Answers every source lookup with a single 720p file served from `server`
+async fn mount_gql(server: &MockServer, expected_lookups: u64) {
+    let body = json!({
+        "data": {
+            "clip": {
+                "playbackAccessToken": { "signature": "sig", "value": "token" },
+                "durationSeconds": 10,
+                "videoQualities": [
+                    { "quality": "720", "frameRate": 30.0, "sourceURL": format!("{}/clip.mp4", server.uri()) }
+                ]
+            }
+        },
+        "extensions": { "durationMilliseconds": 1, "operationName": "VideoAccessToken_Clip", "requestID": "req" }
+    });
+    Mock::given(method("POST"))
+        .and(path("/gql"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .expect(expected_lookups)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn refused_source_is_resolved_again() {
+    let server = MockServer::start().await;
+    mount_gql(&server, 2).await;
+    // The first URL has expired, the re-resolved one goes through
+    Mock::given(method("GET"))
+        .and(path("/clip.mp4"))
+        .respond_with(ResponseTemplate::new(403))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/clip.mp4"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(CLIP_BODY, "video/mp4"))
+        .mount(&server)
+        .await;
+
+    let directory = tempfile::tempdir().unwrap();
+    let client = reqwest::Client::new();
+    let report = download_slugs(None, &client, &["SomeClip".to_string()], directory.path(), &gql(&server), &options()).await;
+
+    assert!(report.failed.is_empty(), "{:?}", report.failed.iter().map(|failed| &failed.error).collect::<Vec<_>>());
+    assert_eq!(report.downloaded.len(), 1);
+    assert_eq!(std::fs::read(directory.path().join("SomeClip.mp4")).unwrap(), CLIP_BODY);
+}
+
+#[tokio::test]
+async fn body_shorter_than_its_content_length_fails() {
+    let response = response_with_headers(&format!("Content-Length: {}\r\n", CLIP_BODY.len() + 100), CLIP_BODY);
+    let url = raw_server(response, false).await;
+    let directory = tempfile::tempdir().unwrap();
+    let file = directory.path().join("SomeClip.mp4");
+
+    let result = download_file(&reqwest::Client::new(), "SomeClip", url, &file, &options()).await;
+
+    assert!(result.is_err());
+    assert!(!file.exists());
+    assert!(!part_path(&file).exists());
+}
+
+#[tokio::test]
+async fn html_page_is_not_saved_as_a_clip() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/expired.mp4"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html><body>Access denied</body></html>", "text/html"))
+        .mount(&server)
+        .await;
+    // Mislabelled pages are caught by the missing mp4 header instead
+    Mock::given(method("GET"))
+        .and(path("/mislabelled.mp4"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html><body>Access denied</body></html>", "video/mp4"))
+        .mount(&server)
+        .await;
+    let directory = tempfile::tempdir().unwrap();
+    let client = reqwest::Client::new();
+
+    for name in ["expired", "mislabelled"] {
+        let url = Url::parse(&format!("{}/{name}.mp4", server.uri())).unwrap();
+        let file = directory.path().join(format!("{name}.mp4"));
+        let result = download_file(&client, name, url, &file, &options()).await;
+        assert!(matches!(result, Err(TwdlError::NotVideo(..))), "{name}");
+        assert!(!file.exists(), "{name}");
+        assert!(!part_path(&file).exists(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn error_mid_stream_leaves_nothing_at_the_final_path() {
+    // One good chunk, then a chunk size that can't be parsed
+    let mut body = format!("{:x}\r\n", CLIP_BODY.len()).into_bytes();
+    body.extend_from_slice(CLIP_BODY);
+    body.extend_from_slice(b"\r\nnot a chunk size\r\n");
+    let url = raw_server(response_with_headers("Transfer-Encoding: chunked\r\n", &body), false).await;
+    let directory = tempfile::tempdir().unwrap();
+    let file = directory.path().join("SomeClip.mp4");
+
+    let result = download_file(&reqwest::Client::new(), "SomeClip", url, &file, &options()).await;
+
+    assert!(matches!(result, Err(TwdlError::Http(_))), "{result:?}");
+    assert!(!file.exists());
+    assert!(!part_path(&file).exists());
+}
+
+#[tokio::test]
+async fn crash_midway_leaves_only_the_part_file() {
+    // Half the body arrives, then the server goes quiet
+    let response = response_with_headers(&format!("Content-Length: {}\r\n", CLIP_BODY.len() * 2), CLIP_BODY);
+    let url = raw_server(response, true).await;
+    let directory = tempfile::tempdir().unwrap();
+    let file = directory.path().join("SomeClip.mp4");
+    let client = reqwest::Client::new();
+    let options = options();
+
+    let mut download = Box::pin(download_file(&client, "SomeClip", url, &file, &options));
+    let stalled = tokio::time::timeout(Duration::from_millis(500), &mut download).await;
+    assert!(stalled.is_err(), "the download should still be waiting for the rest of the body");
+    // A crash runs no destructors, so the download is leaked rather than dropped
+    std::mem::forget(download);
+
+    assert!(part_path(&file).exists());
+    assert!(!file.exists());
+}
+
+// With the clock paused the rate limiter's sleeps finish at once, but still show up in the elapsed time
+#[tokio::test(start_paused = true)]
+async fn max_rate_slows_the_download() {
+    let mut body = CLIP_BODY.to_vec();
+    body.resize(4000, 0);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/clip.mp4"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body.clone(), "video/mp4"))
+        .mount(&server)
+        .await;
+    let url = Url::parse(&format!("{}/clip.mp4", server.uri())).unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let file = directory.path().join("SomeClip.mp4");
+    let options = DownloadOptions { rate_limiter: Some(Arc::new(RateLimiter::new(1000))), ..options() };
+
+    let started = Instant::now();
+    download_file(&reqwest::Client::new(), "SomeClip", url, &file, &options).await.unwrap();
+
+    // The first second's worth goes through at once, the other 3000 bytes take a second per 1000
+    assert!(started.elapsed() >= Duration::from_secs(3), "{:?}", started.elapsed());
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+}
@@ -0,0 +1,43 @@
+use serde_json::json;
+use twdl::{build_http_client, get_video_source_files, GqlConfig, HttpOptions};
+use wiremock::{matchers::{body_partial_json, header, method, path}, Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn resolves_through_the_configured_endpoint() {
+    let server = MockServer::start().await;
+    let body = json!({
+        "data": {
+            "clip": {
+                "playbackAccessToken": { "signature": "sig0", "value": "token value" },
+                "durationSeconds": 12,
+                "videoQualities": [
+                    { "quality": "720", "frameRate": 60.0, "sourceURL": "https://clips.example/720.mp4" },
+                    { "quality": "360", "frameRate": 30.0, "sourceURL": "https://clips.example/360.mp4" }
+                ]
+            }
+        },
+        "extensions": { "durationMilliseconds": 5, "operationName": "VideoAccessToken_Clip", "requestID": "req" }
+    });
+    Mock::given(method("POST"))
+        .and(path("/gql"))
+        .and(header("Client-ID", "test-client"))
+        .and(body_partial_json(json!({ "variables": { "slug": "SomeClip" } })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = build_http_client(&HttpOptions::default()).unwrap();
+    let gql = GqlConfig {
+        client_id: "test-client".to_string(),
+        endpoint: format!("{}/gql", server.uri()),
+        ..GqlConfig::default()
+    };
+    let files = get_video_source_files(&client, "SomeClip", &gql).await.unwrap();
+
+    let urls: Vec<&str> = files.iter().map(|file| file.url.as_str()).collect();
+    assert_eq!(urls, [
+        "https://clips.example/720.mp4?sig=sig0&token=token%20value",
+        "https://clips.example/360.mp4?sig=sig0&token=token%20value"
+    ]);
+}